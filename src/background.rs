@@ -0,0 +1,192 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
+use rayon::prelude::*;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::noise::{Noise, NoiseKind};
+use crate::texture::{Filter, Sampler, Texture, Wrap};
+
+// Cube map faces are looked for under these names, in the usual +x, -x, +y, -y, +z, -z order
+const CUBEMAP_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+const CUBEMAP_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+const CUBEMAP_SAMPLER: Sampler = Sampler::new(Filter::Bilinear, Wrap::Clamp);
+const EQUIRECTANGULAR_SAMPLER: Sampler = Sampler::new(Filter::Bilinear, Wrap::Repeat).with_wrap(Wrap::Repeat, Wrap::Clamp);
+
+// What is seen behind every object. It only depends on the direction of the view
+// ray, as if it were infinitely far away, so it turns with the camera but never
+// moves with it.
+pub enum Background {
+    // Six square images around the camera, indexed like `CUBEMAP_FACES`
+    Cubemap(Vec<Texture>),
+    // A single image spanning 360 degrees of longitude and 180 of latitude
+    Equirectangular(Texture),
+    Starfield(Starfield),
+}
+
+impl Background {
+    // Loads a cube map from a directory with the six faces, or an equirectangular image from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Ok(Background::Equirectangular(Texture::load(path)?));
+        }
+
+        let mut faces = Vec::with_capacity(CUBEMAP_FACES.len());
+        for name in CUBEMAP_FACES {
+            let file = CUBEMAP_EXTENSIONS
+                .iter()
+                .map(|extension| path.join(name).with_extension(extension))
+                .find(|file| file.is_file())
+                .unwrap_or_else(|| path.join(name).with_extension(CUBEMAP_EXTENSIONS[0]));
+            faces.push(Texture::load(file)?);
+        }
+
+        Ok(Background::Cubemap(faces))
+    }
+
+    // Writes the background to every sample of the framebuffer. Depth and stencil
+    // are left alone, so it stays behind whatever is drawn afterwards.
+    pub fn draw(&self, framebuffer: &mut Framebuffer, view_matrix: &Mat4, projection_matrix: &Mat4) {
+        // Only the rotation of the view matters
+        let mut rotation = *view_matrix;
+        rotation.set_column(3, &Vec4::new(0.0, 0.0, 0.0, 1.0));
+        let inverse = (projection_matrix * rotation).try_inverse().unwrap_or_else(Mat4::identity);
+
+        let width = framebuffer.width as f32;
+        let height = framebuffer.height as f32;
+        let mut tiles = framebuffer.tiles();
+
+        tiles.par_iter_mut().for_each(|tile| {
+            for y in tile.rows.clone() {
+                for x in tile.columns.clone() {
+                    // Point on the far plane behind the pixel center, same mapping as the viewport matrix
+                    let ndc = Vec4::new(
+                        (x as f32 + 0.5) / width * 2.0 - 1.0,
+                        1.0 - (y as f32 + 0.5) / height * 2.0,
+                        1.0,
+                        1.0,
+                    );
+                    let point = inverse * ndc;
+                    let direction = (point.xyz() / point.w).normalize();
+
+                    tile.fill(x, y, self.color(&direction));
+                }
+            }
+        });
+    }
+
+    // Color seen along a world-space direction
+    pub fn color(&self, direction: &Vec3) -> Color {
+        match self {
+            Background::Cubemap(faces) => {
+                let (face, uv) = cubemap_uv(direction);
+                CUBEMAP_SAMPLER.sample(&faces[face], uv)
+            }
+            Background::Equirectangular(texture) => {
+                let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+                let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+                EQUIRECTANGULAR_SAMPLER.sample(texture, Vec2::new(u, v))
+            }
+            Background::Starfield(starfield) => starfield.color(direction),
+        }
+    }
+}
+
+// Face a direction goes through and where on it, with the OpenGL cube map conventions
+fn cubemap_uv(direction: &Vec3) -> (usize, Vec2) {
+    let abs = direction.abs();
+    let (face, major, s, t) = if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x > 0.0 { (0, abs.x, -direction.z, -direction.y) } else { (1, abs.x, direction.z, -direction.y) }
+    } else if abs.y >= abs.z {
+        if direction.y > 0.0 { (2, abs.y, direction.x, direction.z) } else { (3, abs.y, direction.x, -direction.z) }
+    } else if direction.z > 0.0 {
+        (4, abs.z, direction.x, -direction.y)
+    } else {
+        (5, abs.z, -direction.x, -direction.y)
+    };
+
+    (face, Vec2::new((s / major + 1.0) * 0.5, (t / major + 1.0) * 0.5))
+}
+
+// Procedural sky: two layers of stars over a faint nebula
+pub struct Starfield {
+    seed: u32,
+    nebula: Noise,
+    // Low frequency noise that decides where the nebula shows up
+    mask: Noise,
+}
+
+// Stars per layer: cells per unit of direction, chance of a star in a cell, and its brightness
+const STAR_LAYERS: [(f32, f32, f32); 2] = [(160.0, 0.06, 1.0), (380.0, 0.05, 0.55)];
+// Radius of a star in cells
+const STAR_RADIUS: f32 = 0.2;
+
+impl Starfield {
+    pub fn new(seed: u32) -> Self {
+        let nebula = Noise::new(NoiseKind::Simplex, seed as i32).frequency(2.5).fbm(3);
+        let mask = Noise::new(NoiseKind::Simplex, seed.wrapping_add(1) as i32).frequency(0.9);
+
+        Starfield { seed, nebula, mask }
+    }
+
+    pub fn color(&self, direction: &Vec3) -> Color {
+        let stars = STAR_LAYERS.iter().enumerate().fold(Color::black(), |color, (layer, &(scale, chance, brightness))| {
+            color + self.star(direction * scale, layer as u32, chance) * brightness
+        });
+
+        self.nebula(direction) + stars
+    }
+
+    // Color of the star of the cell a point falls in, if the cell has one
+    fn star(&self, point: Vec3, layer: u32, chance: f32) -> Color {
+        let cell = point.map(f32::floor);
+        let hash = |channel: u32| {
+            let key = [cell.x as i32 as u32, cell.y as i32 as u32, cell.z as i32 as u32, layer, channel];
+            hash(self.seed, &key)
+        };
+
+        if hash(0) >= chance {
+            return Color::black();
+        }
+
+        // Kept away from the cell borders so the star is never cut off
+        let center = cell + Vec3::new(hash(1), hash(2), hash(3)) * 0.5 + Vec3::new(0.25, 0.25, 0.25);
+        let distance = (point - center).magnitude() / STAR_RADIUS;
+        let intensity = (-distance * distance).exp() * (0.4 + 0.6 * hash(4));
+
+        // From bluish to warm white
+        let tint = Color::new(170, 200, 255).lerp(&Color::new(255, 225, 190), hash(5));
+        tint * intensity
+    }
+
+    fn nebula(&self, direction: &Vec3) -> Color {
+        let mask = self.mask.get01(direction);
+        let coverage = mask.powi(3);
+        // Most of the sky has no nebula, and there the fractal noise is not worth evaluating
+        if coverage < 0.01 {
+            return Color::black();
+        }
+        let density = self.nebula.get01(direction) * coverage;
+
+        // Blue towards the heart of the nebula, violet around it
+        let violet = Color::new(120, 50, 140);
+        let blue = Color::new(30, 70, 150);
+        violet.lerp(&blue, mask * mask) * (density * 0.9)
+    }
+}
+
+// Integer hash of a cell and channel, mapped to [0, 1)
+fn hash(seed: u32, key: &[u32]) -> f32 {
+    let mut h = seed.wrapping_mul(0x9E37_79B9);
+    for &k in key {
+        h ^= k.wrapping_mul(0x85EB_CA6B);
+        h = h.rotate_left(13).wrapping_mul(0xC2B2_AE35);
+    }
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
@@ -18,6 +18,7 @@ impl Camera {
     }
   }
 
+  #[allow(dead_code)]
   pub fn basis_change(&self, vector: &Vec3) -> Vec3 {
     let forward = (self.center - self.eye).normalize();
     let right = forward.cross(&self.up).normalize();
@@ -59,6 +60,21 @@ impl Camera {
     self.has_changed = true;
   }
 
+  // Looks at `target` from `distance` away, keeping the direction the camera looks from
+  pub fn focus(&mut self, target: Vec3, distance: f32) {
+    let direction = (self.eye - self.center).normalize();
+    self.center = target;
+    self.eye = target + direction * distance;
+    self.has_changed = true;
+  }
+
+  // Moves eye and center together, e.g. to follow something that moves
+  pub fn translate(&mut self, offset: Vec3) {
+    self.eye += offset;
+    self.center += offset;
+    self.has_changed = true;
+  }
+
   pub fn move_center(&mut self, direction: Vec3) {
     let radius_vector = self.center - self.eye;
     let radius = radius_vector.magnitude();
@@ -75,6 +91,7 @@ impl Camera {
     self.has_changed = true;
   }
 
+  #[allow(dead_code)]
   pub fn check_if_changed(&mut self) -> bool {
     if self.has_changed {
       self.has_changed = false;
@@ -0,0 +1,181 @@
+use nalgebra_glm::{Mat4, Vec4};
+use crate::vertex::Vertex;
+
+// Frustum planes in homogeneous clip space, as (a, b, c, d) coefficients.
+// A vertex is inside a plane when a*x + b*y + c*z + d*w >= 0, which is the
+// -w <= x, y, z <= w box produced by the perspective matrix.
+const FRUSTUM_PLANES: [[f32; 4]; 6] = [
+    [1.0, 0.0, 0.0, 1.0],   // left
+    [-1.0, 0.0, 0.0, 1.0],  // right
+    [0.0, 1.0, 0.0, 1.0],   // bottom
+    [0.0, -1.0, 0.0, 1.0],  // top
+    [0.0, 0.0, 1.0, 1.0],   // near
+    [0.0, 0.0, -1.0, 1.0],  // far
+];
+
+fn plane_distance(plane: &[f32; 4], position: &Vec4) -> f32 {
+    plane[0] * position.x + plane[1] * position.y + plane[2] * position.z + plane[3] * position.w
+}
+
+// Bit mask with one bit set for every plane the vertex is outside of
+fn outcode(position: &Vec4) -> u8 {
+    FRUSTUM_PLANES
+        .iter()
+        .enumerate()
+        .fold(0, |code, (i, plane)| {
+            if plane_distance(plane, position) < 0.0 { code | (1 << i) } else { code }
+        })
+}
+
+// Clips a triangle against the six frustum planes (Sutherland-Hodgman) and
+// returns the resulting polygon as a triangle fan. Vertices created on a plane
+// get all of their attributes re-interpolated from the original edge.
+pub fn clip_triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<[Vertex; 3]> {
+    let codes = [
+        outcode(&v1.clip_position),
+        outcode(&v2.clip_position),
+        outcode(&v3.clip_position),
+    ];
+
+    // Fully inside: nothing to clip
+    if codes[0] | codes[1] | codes[2] == 0 {
+        return vec![[v1.clone(), v2.clone(), v3.clone()]];
+    }
+
+    // All vertices outside the same plane: nothing to draw
+    if codes[0] & codes[1] & codes[2] != 0 {
+        return Vec::new();
+    }
+
+    let mut polygon = vec![v1.clone(), v2.clone(), v3.clone()];
+    let crossed = codes[0] | codes[1] | codes[2];
+
+    for (i, plane) in FRUSTUM_PLANES.iter().enumerate() {
+        if crossed & (1 << i) == 0 {
+            continue;
+        }
+
+        polygon = clip_polygon(&polygon, plane);
+        if polygon.len() < 3 {
+            return Vec::new();
+        }
+    }
+
+    (1..polygon.len() - 1)
+        .map(|i| [polygon[0].clone(), polygon[i].clone(), polygon[i + 1].clone()])
+        .collect()
+}
+
+fn clip_polygon(polygon: &[Vertex], plane: &[f32; 4]) -> Vec<Vertex> {
+    let mut output = Vec::with_capacity(polygon.len() + 1);
+
+    for i in 0..polygon.len() {
+        let current = &polygon[i];
+        let next = &polygon[(i + 1) % polygon.len()];
+
+        let current_distance = plane_distance(plane, &current.clip_position);
+        let next_distance = plane_distance(plane, &next.clip_position);
+
+        if current_distance >= 0.0 {
+            output.push(current.clone());
+        }
+
+        // The edge crosses the plane: emit the intersection point
+        if (current_distance >= 0.0) != (next_distance >= 0.0) {
+            let t = current_distance / (current_distance - next_distance);
+            output.push(current.lerp(next, t));
+        }
+    }
+
+    output
+}
+
+// Perspective division and viewport transform, applied once the vertex is known to be inside the frustum.
+// 1/w is kept in the w component so the rasterizer can do perspective-correct interpolation.
+pub fn perspective_divide(vertex: &Vertex, viewport_matrix: &Mat4) -> Vertex {
+    let clip = vertex.clip_position;
+    let w = clip.w;
+    let ndc_position = Vec4::new(clip.x / w, clip.y / w, clip.z / w, 1.0);
+
+    let screen_position = viewport_matrix * ndc_position;
+
+    Vertex {
+        transformed_position: Vec4::new(screen_position.x, screen_position.y, screen_position.z, 1.0 / w),
+        ..vertex.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::{Vec2, Vec3};
+
+    fn clip_vertex(x: f32, y: f32, z: f32, w: f32, u: f32, v: f32) -> Vertex {
+        Vertex {
+            clip_position: Vec4::new(x, y, z, w),
+            tex_coords: Vec2::new(u, v),
+            world_position: Vec3::new(x, y, z),
+            ..Default::default()
+        }
+    }
+
+    fn close(a: &Vertex, clip: Vec4, tex_coords: Vec2, world_position: Vec3) -> bool {
+        (a.clip_position - clip).magnitude() < 1e-5
+            && (a.tex_coords - tex_coords).magnitude() < 1e-5
+            && (a.world_position - world_position).magnitude() < 1e-5
+    }
+
+    #[test]
+    fn triangle_inside_is_kept_as_is() {
+        let (v1, v2, v3) = (
+            clip_vertex(-0.5, -0.5, 0.0, 1.0, 0.0, 0.0),
+            clip_vertex(0.5, -0.5, 0.0, 1.0, 1.0, 0.0),
+            clip_vertex(0.0, 0.5, 0.0, 1.0, 0.0, 1.0),
+        );
+        let clipped = clip_triangle(&v1, &v2, &v3);
+
+        assert_eq!(clipped.len(), 1);
+        for (clipped, original) in clipped[0].iter().zip([&v1, &v2, &v3]) {
+            assert!(close(clipped, original.clip_position, original.tex_coords, original.world_position));
+        }
+    }
+
+    #[test]
+    fn triangle_outside_one_plane_is_dropped() {
+        // Every vertex is to the right of x = w
+        let clipped = clip_triangle(
+            &clip_vertex(2.0, 0.0, 0.0, 1.0, 0.0, 0.0),
+            &clip_vertex(3.0, 0.5, 0.0, 1.0, 1.0, 0.0),
+            &clip_vertex(2.5, -0.5, 0.0, 1.0, 0.0, 1.0),
+        );
+        assert!(clipped.is_empty());
+    }
+
+    #[test]
+    fn triangle_across_the_near_plane_becomes_a_quad() {
+        // The first vertex is behind the near plane z = -w, the other two in front of it
+        let v1 = clip_vertex(0.0, 0.0, -3.0, 1.0, 0.0, 0.0);
+        let v2 = clip_vertex(0.5, 0.0, 0.0, 1.0, 1.0, 0.0);
+        let v3 = clip_vertex(-0.5, 0.0, 0.0, 1.0, 0.0, 1.0);
+        let clipped = clip_triangle(&v1, &v2, &v3);
+
+        // Two triangles fanned out of the four corners of the clipped polygon
+        assert_eq!(clipped.len(), 2);
+        let mut corners: Vec<&Vertex> = Vec::new();
+        for vertex in clipped.iter().flatten() {
+            if !corners.iter().any(|corner| close(corner, vertex.clip_position, vertex.tex_coords, vertex.world_position)) {
+                corners.push(vertex);
+            }
+        }
+        assert_eq!(corners.len(), 4);
+        assert!(corners.iter().all(|vertex| vertex.clip_position.z + vertex.clip_position.w >= -1e-5));
+
+        // The new corners sit two thirds of the way from v1 to v2, and from v1 to v3,
+        // with their attributes interpolated along the same edges
+        let on_edge_12 = (Vec4::new(1.0 / 3.0, 0.0, -1.0, 1.0), Vec2::new(2.0 / 3.0, 0.0), Vec3::new(1.0 / 3.0, 0.0, -1.0));
+        let on_edge_31 = (Vec4::new(-1.0 / 3.0, 0.0, -1.0, 1.0), Vec2::new(0.0, 2.0 / 3.0), Vec3::new(-1.0 / 3.0, 0.0, -1.0));
+        for (clip, tex_coords, world_position) in [on_edge_12, on_edge_31] {
+            assert!(corners.iter().any(|corner| close(corner, clip, tex_coords, world_position)));
+        }
+    }
+}
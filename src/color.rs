@@ -5,17 +5,23 @@ pub struct Color {
   r: u8,
   g: u8,
   b: u8,
+  a: u8,
 }
 
 impl Color {
-  // Constructor to initialize the color using r, g, b values as u8
+  // Constructor to initialize the color using r, g, b values as u8, fully opaque
   pub fn new(r: u8, g: u8, b: u8) -> Self {
-    Color { r, g, b }
+    Color { r, g, b, a: 255 }
+  }
+
+  // Constructor with an explicit alpha channel (0 transparent, 255 opaque)
+  pub fn new_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+    Color { r, g, b, a }
   }
 
   // default color
   pub fn black() -> Self {
-    Color { r: 0, g: 0, b: 0 }
+    Color { r: 0, g: 0, b: 0, a: 255 }
   }
 
   // New constructor to initialize the color using r, g, b values as f32 (0.0 to 1.0)
@@ -24,19 +30,25 @@ impl Color {
       r: (r.clamp(0.0, 1.0) * 255.0) as u8,
       g: (g.clamp(0.0, 1.0) * 255.0) as u8,
       b: (b.clamp(0.0, 1.0) * 255.0) as u8,
+      a: 255,
     }
   }
 
+  // Channels as f32 in the 0.0 to 1.0 range, the inverse of `from_float`
+  pub fn to_float(self) -> (f32, f32, f32) {
+    (self.r as f32 / 255.0, self.g as f32 / 255.0, self.b as f32 / 255.0)
+  }
+
   // Function to create a color from a hex value
   pub fn from_hex(hex: u32) -> Self {
     let r = ((hex >> 16) & 0xFF) as u8;
     let g = ((hex >> 8) & 0xFF) as u8;
     let b = (hex & 0xFF) as u8;
-    Color { r, g, b }
+    Color { r, g, b, a: 255 }
   }
 
-  // Function to return the color as a hex value
-  pub fn to_hex(&self) -> u32 {
+  // Function to return the color as a hex value; alpha is not part of it
+  pub fn to_hex(self) -> u32 {
     ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
   }
 
@@ -47,6 +59,20 @@ impl Color {
       r: (self.r as f32 + (other.r as f32 - self.r as f32) * t).round() as u8,
       g: (self.g as f32 + (other.g as f32 - self.g as f32) * t).round() as u8,
       b: (self.b as f32 + (other.b as f32 - self.b as f32) * t).round() as u8,
+      a: (self.a as f32 + (other.a as f32 - self.a as f32) * t).round() as u8,
+    }
+  }
+
+  // Opacity in the 0.0 to 1.0 range
+  pub fn alpha(&self) -> f32 {
+    self.a as f32 / 255.0
+  }
+
+  // Same color with a new opacity in the 0.0 to 1.0 range
+  pub fn with_alpha(self, alpha: f32) -> Self {
+    Color {
+      a: (alpha.clamp(0.0, 1.0) * 255.0).round() as u8,
+      ..self
     }
   }
 
@@ -54,9 +80,10 @@ impl Color {
     self.r == 0 && self.g == 0 && self.b == 0 
   }
 
-  // New blend mode methods
+  // New blend mode methods. How much of `blend` shows is up to its alpha, so a
+  // black source still darkens what is behind it.
   pub fn blend_normal(&self, blend: &Color) -> Color {
-    if blend.is_black() { *self } else { *blend }
+    *blend
   }
 
   pub fn blend_multiply(&self, blend: &Color) -> Color {
@@ -76,9 +103,9 @@ impl Color {
   }
 
   pub fn blend_subtract(&self, blend: &Color) -> Color {
-    let r = (self.r as i16 - blend.r as i16).max(0).min(255) as u8;
-    let g = (self.g as i16 - blend.g as i16).max(0).min(255) as u8;
-    let b = (self.b as i16 - blend.b as i16).max(0).min(255) as u8;
+    let r = (self.r as i16 - blend.r as i16).clamp(0, 255) as u8;
+    let g = (self.g as i16 - blend.g as i16).clamp(0, 255) as u8;
+    let b = (self.b as i16 - blend.b as i16).clamp(0, 255) as u8;
 
     Color::new(r, g, b)
   }
@@ -104,11 +131,12 @@ impl Add for Color {
       r: self.r.saturating_add(other.r),
       g: self.g.saturating_add(other.g),
       b: self.b.saturating_add(other.b),
+      a: self.a.max(other.a),
     }
   }
 }
 
-// Implement multiplication by a constant for Color
+// Implement multiplication by a constant for Color; alpha is left untouched
 use std::ops::Mul;
 
 impl Mul<f32> for Color {
@@ -119,6 +147,7 @@ impl Mul<f32> for Color {
       r: (self.r as f32 * scalar).clamp(0.0, 255.0) as u8,
       g: (self.g as f32 * scalar).clamp(0.0, 255.0) as u8,
       b: (self.b as f32 * scalar).clamp(0.0, 255.0) as u8,
+      a: self.a,
     }
   }
 }
@@ -126,6 +155,6 @@ impl Mul<f32> for Color {
 // Implement display formatting for Color
 impl fmt::Display for Color {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "Color(r: {}, g: {}, b: {})", self.r, self.g, self.b)
+    write!(f, "Color(r: {}, g: {}, b: {}, a: {})", self.r, self.g, self.b, self.a)
   }
 }
@@ -1,36 +1,22 @@
 use nalgebra_glm::{Vec3, Vec2};
 use crate::color::Color;
 
+#[allow(dead_code)]
 pub struct Fragment {
     pub position: Vec2,
     pub color: Color,
     pub depth: f32,
     pub normal: Vec3,
-    pub intensity: f32,
+    // World-space tangent frame along with the normal; not normalized
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
     pub vertex_position: Vec3,
+    pub world_position: Vec3,
     pub tex_coords: Vec2,
+    // Change of the varyings from one pixel to the next across the 2x2 quad,
+    // used to pick the texture LOD
+    pub tex_coords_dx: Vec2,
+    pub tex_coords_dy: Vec2,
+    pub vertex_position_dx: Vec3,
+    pub vertex_position_dy: Vec3,
 }
-
-impl Fragment {
-    pub fn new(
-        position: Vec2,
-        color: Color,
-        depth: f32,
-        normal: Vec3,
-        intensity: f32,
-        vertex_position: Vec3,
-        tex_coords: Vec2,
-    ) -> Self {  
-        Fragment {
-            position,
-            color,
-            depth,
-            normal,
-            intensity,
-            vertex_position,
-            tex_coords,
-        }
-    }
-}
-
-
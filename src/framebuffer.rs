@@ -1,23 +1,111 @@
 // framebuffer.rs
 
+use std::ops::Range;
+use nalgebra_glm::Vec4;
+use crate::line::line;
+use crate::color::Color;
+use crate::render_state::RenderState;
+
+// Width and height of a screen tile, in pixels. Even, so tiles hold whole 2x2 quads.
+// Sample storage is laid out tile by tile: a tile owns a contiguous slice of every
+// buffer, so tiles can be handed to different threads as plain mutable slices.
+pub const TILE_SIZE: usize = 32;
+
+pub const MAX_SAMPLES: usize = 8;
+
+// Sample positions inside a pixel, relative to its center (standard D3D patterns)
+const PATTERN_1X: [(f32, f32); 1] = [(0.0, 0.0)];
+const PATTERN_2X: [(f32, f32); 2] = [(0.25, 0.25), (-0.25, -0.25)];
+const PATTERN_4X: [(f32, f32); 4] = [(-0.125, -0.375), (0.375, -0.125), (-0.375, 0.125), (0.125, 0.375)];
+const PATTERN_8X: [(f32, f32); 8] = [
+    (0.0625, -0.1875), (-0.0625, 0.1875), (0.3125, 0.0625), (-0.1875, -0.3125),
+    (-0.3125, 0.3125), (-0.4375, -0.0625), (0.1875, 0.4375), (0.4375, -0.4375),
+];
+
+pub fn sample_pattern(samples: usize) -> &'static [(f32, f32)] {
+    match samples {
+        1 => &PATTERN_1X,
+        2 => &PATTERN_2X,
+        4 => &PATTERN_4X,
+        8 => &PATTERN_8X,
+        _ => panic!("unsupported MSAA sample count: {}", samples),
+    }
+}
+
+// Columns and rows of the pixels of every tile of a width x height screen, in storage
+// order: rows of tiles from the top, each one from left to right. Tiles on the right
+// and bottom edges are cut to the screen.
+pub fn tile_rects(width: usize, height: usize) -> Vec<(Range<usize>, Range<usize>)> {
+    (0..height)
+        .step_by(TILE_SIZE)
+        .flat_map(|y0| {
+            (0..width)
+                .step_by(TILE_SIZE)
+                .map(move |x0| (x0..(x0 + TILE_SIZE).min(width), y0..(y0 + TILE_SIZE).min(height)))
+        })
+        .collect()
+}
+
+// Tiles across and down a width x height screen
+pub fn tile_grid(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE))
+}
+
+// Splits a buffer stored tile by tile, with `samples` values per pixel, into one slice per tile
+pub fn split_tiles<T>(mut data: &mut [T], width: usize, height: usize, samples: usize) -> Vec<(Range<usize>, Range<usize>, &mut [T])> {
+    tile_rects(width, height)
+        .into_iter()
+        .map(|(columns, rows)| {
+            let (tile, rest) = std::mem::take(&mut data).split_at_mut(columns.len() * rows.len() * samples);
+            data = rest;
+            (columns, rows, tile)
+        })
+        .collect()
+}
+
+// Position of a pixel in a buffer stored tile by tile, counted in pixels
+fn tiled_index(width: usize, height: usize, x: usize, y: usize) -> usize {
+    let (x0, y0) = (x - x % TILE_SIZE, y - y % TILE_SIZE);
+    let tile_width = TILE_SIZE.min(width - x0);
+    let tile_height = TILE_SIZE.min(height - y0);
+
+    // Full rows of tiles above, then the tiles to the left in this row of tiles
+    y0 * width + x0 * tile_height + (y - y0) * tile_width + (x - x0)
+}
+
 pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
+    // Resolved colors, row by row
     pub buffer: Vec<u32>,
-    pub zbuffer: Vec<f32>,
+    // Per-sample colors, depth and stencil, stored tile by tile
+    sample_buffer: Vec<u32>,
+    zbuffer: Vec<f32>,
+    stencil: Vec<u8>,
+    samples: usize,
     background_color: u32,
-    current_color: u32,
 }
 
 impl Framebuffer {
     pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer::with_samples(width, height, 1)
+    }
+
+    // Framebuffer with multisample anti-aliasing: color, depth and coverage are
+    // stored per sample and averaged into `buffer` by `resolve`, which has to run
+    // before `buffer` is read even with a single sample
+    pub fn with_samples(width: usize, height: usize, samples: usize) -> Self {
+        sample_pattern(samples);
+
         Framebuffer {
             width,
             height,
             buffer: vec![0; width * height],
-            zbuffer: vec![f32::INFINITY; width * height],
+            sample_buffer: vec![0; width * height * samples],
+            zbuffer: vec![f32::INFINITY; width * height * samples],
+            stencil: vec![0; width * height * samples],
+            samples,
             background_color: 0x000000,
-            current_color: 0xFFFFFF,
         }
     }
 
@@ -25,26 +113,290 @@ impl Framebuffer {
         for pixel in self.buffer.iter_mut() {
             *pixel = self.background_color;
         }
+        for sample in self.sample_buffer.iter_mut() {
+            *sample = self.background_color;
+        }
         for depth in self.zbuffer.iter_mut() {
             *depth = f32::INFINITY;
         }
+        for stencil in self.stencil.iter_mut() {
+            *stencil = 0;
+        }
+    }
+
+    pub fn set_background_color(&mut self, color: u32) {
+        self.background_color = color;
     }
 
-    pub fn point(&mut self, x: usize, y: usize, depth: f32) {
-        if x < self.width && y < self.height {
-            let index = y * self.width + x;
-            if self.zbuffer[index] > depth {
-                self.buffer[index] = self.current_color;
-                self.zbuffer[index] = depth;
+    // Averages the samples of every pixel into `buffer`, row by row
+    pub fn resolve(&mut self) {
+        let n = self.samples as u32;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = tiled_index(self.width, self.height, x, y) * self.samples;
+                let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+                for &sample in &self.sample_buffer[index..index + self.samples] {
+                    r += (sample >> 16) & 0xFF;
+                    g += (sample >> 8) & 0xFF;
+                    b += sample & 0xFF;
+                }
+                self.buffer[y * self.width + x] = ((r / n) << 16) | ((g / n) << 8) | (b / n);
             }
         }
     }
 
-    pub fn set_background_color(&mut self, color: u32) {
-        self.background_color = color;
+    // Depth of the first sample of every pixel, row by row
+    pub fn depths(&self) -> Vec<f32> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.zbuffer[tiled_index(self.width, self.height, x, y) * self.samples])
+            .collect()
+    }
+
+    // Splits the framebuffer into disjoint tiles that can be written from different threads
+    pub fn tiles(&mut self) -> Vec<Tile<'_>> {
+        let (width, height, samples) = (self.width, self.height, self.samples);
+
+        split_tiles(&mut self.sample_buffer, width, height, samples)
+            .into_iter()
+            .zip(split_tiles(&mut self.zbuffer, width, height, samples))
+            .zip(split_tiles(&mut self.stencil, width, height, samples))
+            .map(|(((columns, rows, buffer), (_, _, zbuffer)), (_, _, stencil))| Tile {
+                columns,
+                rows,
+                sample_pattern: sample_pattern(samples),
+                buffer,
+                zbuffer,
+                stencil,
+            })
+            .collect()
+    }
+}
+
+// Line and point primitives are drawn slightly towards the camera so they win
+// the depth test against the surface they outline
+const WIREFRAME_DEPTH_BIAS: f32 = 1e-4;
+
+// A tile of the framebuffer, addressed with framebuffer coordinates
+pub struct Tile<'a> {
+    pub columns: Range<usize>,
+    pub rows: Range<usize>,
+    pub sample_pattern: &'static [(f32, f32)],
+    buffer: &'a mut [u32],
+    zbuffer: &'a mut [f32],
+    stencil: &'a mut [u8],
+}
+
+impl Tile<'_> {
+    // Index of the first sample of a pixel
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if self.columns.contains(&x) && self.rows.contains(&y) {
+            let offset = (y - self.rows.start) * self.columns.len() + x - self.columns.start;
+            Some(offset * self.sample_pattern.len())
+        } else {
+            None
+        }
+    }
+
+    // Stencil and depth tests without writing anything, used to reject fragments
+    // before shading them. Returns the covered samples that pass both.
+    pub fn depth_test(&self, x: usize, y: usize, mask: u32, depths: &[f32], state: &RenderState) -> u32 {
+        let Some(index) = self.index(x, y) else {
+            return 0;
+        };
+
+        (0..self.sample_pattern.len())
+            .filter(|&s| {
+                mask & (1 << s) != 0
+                    && state.stencil.passes(self.stencil[index + s])
+                    && self.zbuffer[index + s] > depths[s]
+            })
+            .fold(0, |passed, s| passed | (1 << s))
+    }
+
+    // Writes the shaded color to every covered sample that passes the stencil and
+    // depth tests, blending it with the stored color according to the render state
+    pub fn point(&mut self, x: usize, y: usize, mask: u32, depths: &[f32], color: Color, state: &RenderState) {
+        self.write_samples(x, y, mask, depths, Some(color), state);
+    }
+
+    // Runs the tests for a fragment that was rejected before shading, so the
+    // stencil fail and depth-fail operations still take effect
+    pub fn reject(&mut self, x: usize, y: usize, mask: u32, depths: &[f32], state: &RenderState) {
+        if state.stencil.enabled {
+            self.write_samples(x, y, mask, depths, None, state);
+        }
+    }
+
+    // Runs the tests and writes depth and stencil for a fragment without a color,
+    // as the deferred geometry pass does. Returns the samples that passed.
+    pub fn cover(&mut self, x: usize, y: usize, mask: u32, depths: &[f32], state: &RenderState) -> u32 {
+        self.write_samples(x, y, mask, depths, None, state)
+    }
+
+    fn write_samples(&mut self, x: usize, y: usize, mask: u32, depths: &[f32], color: Option<Color>, state: &RenderState) -> u32 {
+        let Some(index) = self.index(x, y) else {
+            return 0;
+        };
+        let mut passed = 0;
+        let stencil = &state.stencil;
+
+        for (s, &depth) in depths.iter().enumerate().take(self.sample_pattern.len()) {
+            if mask & (1 << s) == 0 {
+                continue;
+            }
+            let i = index + s;
+
+            if !stencil.passes(self.stencil[i]) {
+                if stencil.enabled {
+                    self.stencil[i] = stencil.update(self.stencil[i], stencil.fail);
+                }
+                continue;
+            }
+
+            if self.zbuffer[i] <= depth {
+                if stencil.enabled {
+                    self.stencil[i] = stencil.update(self.stencil[i], stencil.depth_fail);
+                }
+                continue;
+            }
+
+            passed |= 1 << s;
+            if stencil.enabled {
+                self.stencil[i] = stencil.update(self.stencil[i], stencil.pass);
+            }
+            if let (Some(color), true) = (color, state.color_write) {
+                let dst = Color::from_hex(self.buffer[i]);
+                self.buffer[i] = state.blend_mode.apply(dst, color).to_hex();
+            }
+            if state.depth_write {
+                self.zbuffer[i] = depth;
+            }
+        }
+
+        passed
+    }
+
+    // Writes a color to one sample of a pixel without any test, as the deferred
+    // lighting pass does for the samples the geometry pass covered
+    pub fn set_sample(&mut self, x: usize, y: usize, sample: usize, color: Color) {
+        if let Some(index) = self.index(x, y) {
+            self.buffer[index + sample] = color.to_hex();
+        }
+    }
+
+    // Writes a color to every sample of a pixel without touching depth or stencil,
+    // as the background does
+    pub fn fill(&mut self, x: usize, y: usize, color: Color) {
+        let Some(index) = self.index(x, y) else {
+            return;
+        };
+
+        let color = color.to_hex();
+        for sample in &mut self.buffer[index..index + self.sample_pattern.len()] {
+            *sample = color;
+        }
+    }
+
+    // Depth-tested line between two screen-space positions, covering every sample of its pixels
+    pub fn line(&mut self, from: &Vec4, to: &Vec4, color: Color, state: &RenderState) {
+        let mask = self.full_mask();
+
+        line(from, to, self.columns.clone(), self.rows.clone(), |x, y, depth| {
+            let depths = [depth - WIREFRAME_DEPTH_BIAS; MAX_SAMPLES];
+            self.point(x, y, mask, &depths, color, state);
+        });
+    }
+
+    // Depth-tested square dot centered on a screen-space position
+    pub fn dot(&mut self, position: &Vec4, size: usize, color: Color, state: &RenderState) {
+        let mask = self.full_mask();
+        let depths = [position.z - WIREFRAME_DEPTH_BIAS; MAX_SAMPLES];
+        let half = (size / 2) as f32;
+
+        let (x0, y0) = ((position.x - half).floor(), (position.y - half).floor());
+        for y in 0..size {
+            for x in 0..size {
+                let (px, py) = (x0 + x as f32, y0 + y as f32);
+                if px >= 0.0 && py >= 0.0 {
+                    self.point(px as usize, py as usize, mask, &depths, color, state);
+                }
+            }
+        }
+    }
+
+    fn full_mask(&self) -> u32 {
+        (1 << self.sample_pattern.len()) - 1
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_state::{CompareFunc, StencilOp, StencilState};
 
-    pub fn set_current_color(&mut self, color: u32) {
-        self.current_color = color;
+    fn draw(framebuffer: &mut Framebuffer, depths: [f32; 2], color: Color, state: &RenderState) -> u32 {
+        framebuffer.tiles()[0].write_samples(0, 0, 0b11, &depths, Some(color), state)
+    }
+
+    #[test]
+    fn stencil_ops_follow_the_stencil_and_depth_tests() {
+        let mut framebuffer = Framebuffer::with_samples(1, 1, 2);
+        framebuffer.clear();
+        let red = Color::new(255, 0, 0);
+        let state = RenderState {
+            stencil: StencilState {
+                fail: StencilOp::Zero,
+                depth_fail: StencilOp::IncrementWrap,
+                ..StencilState::write(5)
+            },
+            ..RenderState::default()
+        };
+
+        // Both samples pass and take the reference value
+        assert_eq!(draw(&mut framebuffer, [0.5, 0.5], red, &state), 0b11);
+        assert_eq!(framebuffer.stencil, vec![5, 5]);
+
+        // The second sample is behind the stored depth: depth-fail op, no color, no depth
+        let blue = Color::new(0, 0, 255);
+        assert_eq!(draw(&mut framebuffer, [0.3, 0.9], blue, &state), 0b01);
+        assert_eq!(framebuffer.stencil, vec![5, 6]);
+        assert_eq!(framebuffer.zbuffer, vec![0.3, 0.5]);
+        assert_eq!(framebuffer.sample_buffer, vec![blue.to_hex(), red.to_hex()]);
+
+        // A failing stencil test runs the fail op whatever the depth
+        let only_six = RenderState {
+            stencil: StencilState { func: CompareFunc::Equal, reference: 6, ..state.stencil },
+            ..state
+        };
+        let green = Color::new(0, 255, 0);
+        assert_eq!(draw(&mut framebuffer, [0.1, 0.1], green, &only_six), 0b10);
+        assert_eq!(framebuffer.stencil, vec![0, 6]);
+        assert_eq!(framebuffer.sample_buffer, vec![blue.to_hex(), green.to_hex()]);
+    }
+
+    #[test]
+    fn tiles_cover_every_pixel_once_and_resolve_row_by_row() {
+        // Neither side is a multiple of the tile size, so the last tiles are cut
+        let (width, height) = (70, 40);
+        let rects = tile_rects(width, height);
+        assert_eq!(rects.len(), 3 * 2);
+        assert_eq!(rects.iter().map(|(columns, rows)| columns.len() * rows.len()).sum::<usize>(), width * height);
+
+        let mut framebuffer = Framebuffer::with_samples(width, height, 2);
+        for tile in framebuffer.tiles().iter_mut() {
+            for y in tile.rows.clone() {
+                for x in tile.columns.clone() {
+                    for s in 0..2 {
+                        tile.set_sample(x, y, s, Color::from_hex((y * width + x) as u32));
+                    }
+                }
+            }
+        }
+        framebuffer.resolve();
+
+        for (i, &pixel) in framebuffer.buffer.iter().enumerate() {
+            assert_eq!(pixel, i as u32, "pixel ({}, {})", i % width, i / width);
+        }
     }
 }
@@ -0,0 +1,234 @@
+use std::ops::Range;
+use nalgebra_glm::Vec3;
+use rayon::prelude::*;
+use crate::color::Color;
+use crate::framebuffer::{split_tiles, Framebuffer};
+use crate::light::{shade, Light};
+use crate::surface::{Surface, NO_MATERIAL};
+
+// What the lighting pass writes to the screen: the lit image, or one of the
+// G-buffer targets for debugging
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GBufferOutput {
+    Lit,
+    Albedo,
+    Specular,
+    Normal,
+    Position,
+    Emissive,
+    Material,
+}
+
+impl GBufferOutput {
+    pub fn next(self) -> Self {
+        match self {
+            GBufferOutput::Lit => GBufferOutput::Albedo,
+            GBufferOutput::Albedo => GBufferOutput::Specular,
+            GBufferOutput::Specular => GBufferOutput::Normal,
+            GBufferOutput::Normal => GBufferOutput::Position,
+            GBufferOutput::Position => GBufferOutput::Emissive,
+            GBufferOutput::Emissive => GBufferOutput::Material,
+            GBufferOutput::Material => GBufferOutput::Lit,
+        }
+    }
+}
+
+// Surface attributes written by the geometry pass of the deferred path, one set per
+// sample so edges between objects keep their anti-aliasing under MSAA. That costs
+// memory per sample, and the lighting pass shades every distinct surface in a
+// pixel instead of once per pixel. Depth and stencil stay in the framebuffer the
+// G-buffer is rendered along with, which must have the same sample count.
+pub struct GBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub output: GBufferOutput,
+    samples: usize,
+    albedo: Vec<Color>,
+    ambient: Vec<Color>,
+    // Specular color, with the shininess alongside
+    specular: Vec<(Color, f32)>,
+    normal: Vec<Vec3>,
+    position: Vec<Vec3>,
+    emissive: Vec<Color>,
+    material: Vec<u8>,
+}
+
+impl GBuffer {
+    // The targets are only allocated by the first `clear`, so a G-buffer that is
+    // never used costs nothing even with many samples
+    pub fn new(width: usize, height: usize, samples: usize) -> Self {
+        GBuffer {
+            width,
+            height,
+            output: GBufferOutput::Lit,
+            samples,
+            albedo: Vec::new(),
+            ambient: Vec::new(),
+            specular: Vec::new(),
+            normal: Vec::new(),
+            position: Vec::new(),
+            emissive: Vec::new(),
+            material: Vec::new(),
+        }
+    }
+
+    // Only the material ID needs resetting, the other targets are ignored where it is empty
+    pub fn clear(&mut self) {
+        let size = self.width * self.height * self.samples;
+        if self.material.len() != size {
+            self.albedo = vec![Color::black(); size];
+            self.ambient = vec![Color::black(); size];
+            self.specular = vec![(Color::black(), 1.0); size];
+            self.normal = vec![Vec3::zeros(); size];
+            self.position = vec![Vec3::zeros(); size];
+            self.emissive = vec![Color::black(); size];
+            self.material = vec![NO_MATERIAL; size];
+        }
+
+        for material in self.material.iter_mut() {
+            *material = NO_MATERIAL;
+        }
+    }
+
+    // Lights the stored surfaces and writes the result to the samples of the framebuffer
+    // that the geometry pass covered, in parallel over tiles. Neighboring samples of a
+    // pixel that hold the same surface are only lit once.
+    pub fn lighting_pass(&mut self, framebuffer: &mut Framebuffer, lights: &[Light], ambient: Color, eye: &Vec3) {
+        let output = self.output;
+        let samples = self.samples;
+        let mut tiles = framebuffer.tiles();
+
+        tiles.par_iter_mut().zip(self.tiles().par_iter()).for_each(|(tile, surfaces)| {
+            for y in surfaces.rows.clone() {
+                for x in surfaces.columns.clone() {
+                    let mut lit: Option<(Surface, Color)> = None;
+                    for s in 0..samples {
+                        let Some(surface) = surfaces.load(x, y, s) else {
+                            continue;
+                        };
+                        let color = match lit {
+                            Some((previous, color)) if previous.position == surface.position && previous.material == surface.material => color,
+                            _ => output_color(&surface, output, lights, ambient, eye),
+                        };
+                        tile.set_sample(x, y, s, color);
+                        lit = Some((surface, color));
+                    }
+                }
+            }
+        });
+    }
+
+    // Splits the targets into the same tiles as `Framebuffer::tiles`
+    pub fn tiles(&mut self) -> Vec<GBufferTile<'_>> {
+        let (width, height, samples) = (self.width, self.height, self.samples);
+
+        split_tiles(&mut self.albedo, width, height, samples)
+            .into_iter()
+            .zip(split_tiles(&mut self.ambient, width, height, samples))
+            .zip(split_tiles(&mut self.specular, width, height, samples))
+            .zip(split_tiles(&mut self.normal, width, height, samples))
+            .zip(split_tiles(&mut self.position, width, height, samples))
+            .zip(split_tiles(&mut self.emissive, width, height, samples))
+            .zip(split_tiles(&mut self.material, width, height, samples))
+            .map(|((((((albedo, ambient), specular), normal), position), emissive), material)| {
+                let (columns, rows, albedo) = albedo;
+                GBufferTile {
+                    samples,
+                    columns,
+                    rows,
+                    albedo,
+                    ambient: ambient.2,
+                    specular: specular.2,
+                    normal: normal.2,
+                    position: position.2,
+                    emissive: emissive.2,
+                    material: material.2,
+                }
+            })
+            .collect()
+    }
+}
+
+// A tile of the G-buffer, addressed with screen coordinates
+pub struct GBufferTile<'a> {
+    samples: usize,
+    pub columns: Range<usize>,
+    pub rows: Range<usize>,
+    albedo: &'a mut [Color],
+    ambient: &'a mut [Color],
+    specular: &'a mut [(Color, f32)],
+    normal: &'a mut [Vec3],
+    position: &'a mut [Vec3],
+    emissive: &'a mut [Color],
+    material: &'a mut [u8],
+}
+
+impl GBufferTile<'_> {
+    // Index of the first sample of a pixel
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if self.columns.contains(&x) && self.rows.contains(&y) {
+            let offset = (y - self.rows.start) * self.columns.len() + x - self.columns.start;
+            Some(offset * self.samples)
+        } else {
+            None
+        }
+    }
+
+    // Stores the surface in the samples of a pixel set in `mask`
+    pub fn store(&mut self, x: usize, y: usize, mask: u32, surface: &Surface) {
+        let Some(index) = self.index(x, y) else {
+            return;
+        };
+
+        for s in (0..self.samples).filter(|s| mask & (1 << s) != 0) {
+            let i = index + s;
+            self.albedo[i] = surface.albedo;
+            self.ambient[i] = surface.ambient;
+            self.specular[i] = (surface.specular, surface.shininess);
+            self.normal[i] = surface.normal;
+            self.position[i] = surface.position;
+            self.emissive[i] = surface.emissive;
+            self.material[i] = surface.material;
+        }
+    }
+
+    // The surface stored at a sample of a pixel, if any
+    pub fn load(&self, x: usize, y: usize, sample: usize) -> Option<Surface> {
+        let i = self.index(x, y)? + sample;
+        if self.material[i] == NO_MATERIAL {
+            return None;
+        }
+
+        let (specular, shininess) = self.specular[i];
+        Some(Surface {
+            albedo: self.albedo[i],
+            ambient: self.ambient[i],
+            specular,
+            shininess,
+            emissive: self.emissive[i],
+            normal: self.normal[i],
+            position: self.position[i],
+            material: self.material[i],
+        })
+    }
+}
+
+// False colors to tell materials apart in the material ID view
+const MATERIAL_COLORS: [u32; 8] = [
+    0xE6194B, 0x3CB44B, 0xFFE119, 0x4363D8, 0xF58231, 0x911EB4, 0x46F0F0, 0xF032E6,
+];
+
+// Final color of a G-buffer pixel for the selected output
+fn output_color(surface: &Surface, output: GBufferOutput, lights: &[Light], ambient: Color, eye: &Vec3) -> Color {
+    let to_color = |v: Vec3| Color::from_float(v.x * 0.5 + 0.5, v.y * 0.5 + 0.5, v.z * 0.5 + 0.5);
+
+    match output {
+        GBufferOutput::Lit => shade(surface, lights, ambient, eye),
+        GBufferOutput::Albedo => surface.albedo,
+        GBufferOutput::Specular => surface.specular,
+        GBufferOutput::Normal => to_color(surface.normal.normalize()),
+        GBufferOutput::Position => to_color(surface.position),
+        GBufferOutput::Emissive => surface.emissive,
+        GBufferOutput::Material => Color::from_hex(MATERIAL_COLORS[surface.material as usize % MATERIAL_COLORS.len()]),
+    }
+}
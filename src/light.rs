@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::shadow::ShadowMap;
+use crate::surface::Surface;
+
+// Where the light comes from. Positions and directions are in world space, and
+// directions point the way the light travels.
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    // Infinitely far away, shining the same way over the whole scene
+    Directional { direction: Vec3 },
+    // Shining in every direction from a position. At `range` the light is down to half.
+    Point { position: Vec3, range: f32 },
+    // A point light limited to a cone, fading out between the inner and outer angles (radians)
+    Spot { position: Vec3, direction: Vec3, range: f32, inner_angle: f32, outer_angle: f32 },
+}
+
+#[derive(Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Color,
+    pub intensity: f32,
+    pub casts_shadows: bool,
+    // Rendered every frame for the lights that cast shadows
+    pub shadow: Option<Arc<ShadowMap>>,
+}
+
+impl Light {
+    fn new(kind: LightKind, color: Color, intensity: f32) -> Self {
+        Light {
+            kind,
+            color,
+            intensity,
+            casts_shadows: true,
+            shadow: None,
+        }
+    }
+
+    pub fn directional(direction: Vec3, color: Color, intensity: f32) -> Self {
+        Light::new(LightKind::Directional { direction: direction.normalize() }, color, intensity)
+    }
+
+    pub fn point(position: Vec3, range: f32, color: Color, intensity: f32) -> Self {
+        Light::new(LightKind::Point { position, range }, color, intensity)
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, range: f32, inner_angle: f32, outer_angle: f32, color: Color, intensity: f32) -> Self {
+        let direction = direction.normalize();
+        Light::new(LightKind::Spot { position, direction, range, inner_angle, outer_angle }, color, intensity)
+    }
+
+    // Unit vector from a world-space point towards the light
+    pub fn direction_to(&self, position: &Vec3) -> Vec3 {
+        match self.kind {
+            LightKind::Directional { direction } => -direction,
+            LightKind::Point { position: light_position, .. } | LightKind::Spot { position: light_position, .. } => {
+                (light_position - position).normalize()
+            }
+        }
+    }
+
+    // Fraction of the intensity that reaches a point, from distance falloff and the spot cone
+    pub fn attenuation(&self, position: &Vec3) -> f32 {
+        let falloff = |light_position: Vec3, range: f32| {
+            let distance = (light_position - position).magnitude();
+            1.0 / (1.0 + (distance / range).powi(2))
+        };
+
+        match self.kind {
+            LightKind::Directional { .. } => 1.0,
+            LightKind::Point { position: light_position, range } => falloff(light_position, range),
+            LightKind::Spot { position: light_position, direction, range, inner_angle, outer_angle } => {
+                let cos_angle = (position - light_position).normalize().dot(&direction);
+                let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+                let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(1e-4)).clamp(0.0, 1.0);
+                let cone = t * t * (3.0 - 2.0 * t);
+
+                falloff(light_position, range) * cone
+            }
+        }
+    }
+
+    // How much of the light reaches a point, after its shadow map
+    pub fn visibility(&self, position: &Vec3, normal: &Vec3) -> f32 {
+        self.shadow.as_ref().map_or(1.0, |shadow| shadow.visibility(position, normal))
+    }
+}
+
+// Blinn-Phong lighting of a surface seen from `eye`: its emission, plus the ambient
+// light, plus the diffuse and specular reflection of every light. The surface keeps
+// its opacity.
+pub fn shade(surface: &Surface, lights: &[Light], ambient: Color, eye: &Vec3) -> Color {
+    let normal = surface.normal.normalize();
+    let position = surface.position;
+    let view = (eye - position).normalize();
+
+    let color = lights.iter().fold(surface.emissive + surface.ambient.blend_multiply(&ambient), |color, light| {
+        let to_light = light.direction_to(&position);
+        let cos_theta = normal.dot(&to_light);
+        if cos_theta <= 0.0 {
+            return color;
+        }
+
+        let radiance = light.intensity * light.attenuation(&position) * light.visibility(&position, &normal);
+        let diffuse = surface.albedo.blend_multiply(&light.color) * (cos_theta * radiance);
+        if surface.specular.is_black() {
+            return color + diffuse;
+        }
+
+        let half = (to_light + view).normalize();
+        let highlight = normal.dot(&half).max(0.0).powf(surface.shininess);
+        color + diffuse + surface.specular.blend_multiply(&light.color) * (highlight * radiance)
+    });
+
+    color.with_alpha(surface.albedo.alpha())
+}
@@ -0,0 +1,24 @@
+use std::ops::Range;
+use nalgebra_glm::Vec4;
+
+// Walks the pixels of the segment between two screen-space positions inside `columns` and
+// `rows` (DDA), interpolating depth linearly, which is exact for z/w in screen space
+pub fn line<F: FnMut(usize, usize, f32)>(from: &Vec4, to: &Vec4, columns: Range<usize>, rows: Range<usize>, mut emit: F) {
+    let dx = to.x - from.x;
+    let dy = to.y - from.y;
+    let dz = to.z - from.z;
+
+    let steps = dx.abs().max(dy.abs()).ceil().max(1.0) as usize;
+
+    for i in 0..=steps {
+        let t = i as f32 / steps as f32;
+        let x = (from.x + dx * t).floor();
+        let y = (from.y + dy * t).floor();
+
+        if x < 0.0 || y < 0.0 || !columns.contains(&(x as usize)) || !rows.contains(&(y as usize)) {
+            continue;
+        }
+
+        emit(x as usize, y as usize, from.z + dz * t);
+    }
+}
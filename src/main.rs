@@ -1,6 +1,8 @@
 use nalgebra_glm::{Vec3, Mat4, look_at, perspective};
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 use std::f32::consts::PI;
+use std::sync::Arc;
+use rayon::prelude::*;
 
 mod framebuffer;
 mod triangle;
@@ -9,21 +11,56 @@ mod model;
 mod color;
 mod fragment;
 mod shaders;
+mod shader;
 mod camera;
-
-use framebuffer::Framebuffer;
+mod clipping;
+mod render_state;
+mod line;
+mod texture;
+mod render_target;
+mod surface;
+mod light;
+mod gbuffer;
+mod shadow;
+mod material;
+mod background;
+mod noise;
+mod scene;
+mod scene_graph;
+
+use framebuffer::{tile_grid, Framebuffer, TILE_SIZE};
 use vertex::Vertex;
-use model::Obj;
+use model::IndexedMesh;
+use material::Material;
+use color::Color;
 use camera::Camera;
-use triangle::triangle;
-use shaders::{vertex_shader, fragment_shader};
-
-pub struct Uniforms {
+use triangle::{triangle, interpolate_fragment, pixel_bounds, signed_area, Interpolation};
+use shader::{Program, ShaderRegistry};
+use clipping::{clip_triangle, perspective_divide};
+use render_state::{CompareFunc, PolygonMode, RenderState, StencilState};
+use texture::Texture;
+use light::{Light, LightKind};
+use gbuffer::GBuffer;
+use background::Background;
+use scene::{Scene, SceneObject};
+use shadow::{directional_light_matrices, point_light_matrices, DepthMap, ShadowMap, ShadowSettings};
+
+#[derive(Clone)]
+pub struct Uniforms<'a> {
     model_matrix: Mat4,
     view_matrix: Mat4,
     projection_matrix: Mat4,
     viewport_matrix: Mat4,
     time: u32,
+    // Texture bound to the draw, e.g. the image of a render target
+    texture: Option<Arc<Texture>>,
+    // Scene lights, with their shadow maps, and the ambient light. Borrowed from the
+    // render context so draws and face groups share them without copying.
+    lights: &'a [Light],
+    ambient: Color,
+    camera_position: Vec3,
+    // Material of the face group being drawn
+    material: Material,
 }
 
 fn create_model_matrix(translation: Vec3, scale: f32, rotation: Vec3) -> Mat4 {
@@ -87,125 +124,346 @@ fn create_viewport_matrix(width: f32, height: f32) -> Mat4 {
     )
 }
 
-fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], id: f32) {
-    
-    // Vertex Shader Stage
-    let mut transformed_vertices = Vec::with_capacity(vertex_array.len());
-    for vertex in vertex_array {
-        let transformed = vertex_shader(vertex, uniforms);
-        transformed_vertices.push(transformed);
+const WIREFRAME_COLOR: u32 = 0xE0E0E0;
+const POINT_SIZE: usize = 3;
+
+// Vertex Shader Stage
+// Runs once per unique vertex; the result acts as the post-transform cache
+// that primitive assembly reads through the index buffer.
+fn shade_vertices(uniforms: &Uniforms, mesh: &IndexedMesh, program: Program) -> Vec<Vertex> {
+    mesh.vertices
+        .par_iter()
+        .map(|vertex| program.shade_vertex(vertex, uniforms))
+        .collect()
+}
+
+// Primitive Assembly and Clipping Stage
+// Clips and culls the triangles of a range of the index buffer. Returns the visible
+// triangles in screen space, in submission order.
+fn assemble_triangles(uniforms: &Uniforms, transformed_vertices: &[Vertex], indices: &[u32], state: &RenderState) -> Vec<[Vertex; 3]> {
+    indices
+        .par_chunks_exact(3)
+        .flat_map_iter(|tri| {
+            let v1 = &transformed_vertices[tri[0] as usize];
+            let v2 = &transformed_vertices[tri[1] as usize];
+            let v3 = &transformed_vertices[tri[2] as usize];
+
+            // Perspective division only happens on vertices inside the frustum
+            clip_triangle(v1, v2, v3)
+                .into_iter()
+                .map(|clipped| clipped.map(|v| perspective_divide(&v, &uniforms.viewport_matrix)))
+                // Face culling from the screen-space winding
+                .filter(|[v1, v2, v3]| !state.is_culled(signed_area(v1, v2, v3)))
+        })
+        .collect()
+}
+
+// Shades the vertices once and hands every face group of the mesh to `draw`, as
+// screen-space triangles together with uniforms that carry the group's material
+fn for_each_group<F>(uniforms: &Uniforms, mesh: &IndexedMesh, program: Program, state: &RenderState, mut draw: F)
+where
+    F: FnMut(&Uniforms, &[[Vertex; 3]]),
+{
+    let transformed_vertices = shade_vertices(uniforms, mesh, program);
+
+    for group in &mesh.groups {
+        let triangles = assemble_triangles(uniforms, &transformed_vertices, &mesh.indices[group.indices.clone()], state);
+        let uniforms = Uniforms {
+            material: group.material,
+            ..uniforms.clone()
+        };
+
+        draw(&uniforms, &triangles);
     }
+}
 
-    // Primitive Assembly Stage
-    let mut triangles = Vec::new();
-    for i in (0..transformed_vertices.len()).step_by(3) {
-        if i + 2 < transformed_vertices.len() {
-            triangles.push([
-                transformed_vertices[i].clone(),
-                transformed_vertices[i + 1].clone(),
-                transformed_vertices[i + 2].clone(),
-            ]);
+// Binning Stage
+// Every tile gets the indices of the triangles whose bounding box touches it, in
+// submission order. Bins follow the order of `Framebuffer::tiles`.
+fn bin_triangles(triangles: &[[Vertex; 3]], width: usize, height: usize) -> Vec<Vec<usize>> {
+    let (tiles_x, tiles_y) = tile_grid(width, height);
+    let mut bins = vec![Vec::new(); tiles_x * tiles_y];
+    for (i, [v1, v2, v3]) in triangles.iter().enumerate() {
+        let (columns, rows) = pixel_bounds(v1, v2, v3);
+        if columns.is_empty() || rows.is_empty() || columns.start >= width || rows.start >= height {
+            continue;
+        }
+        let last_x = ((columns.end - 1) / TILE_SIZE).min(tiles_x - 1);
+        let last_y = ((rows.end - 1) / TILE_SIZE).min(tiles_y - 1);
+        for tile_y in rows.start / TILE_SIZE..=last_y {
+            for tile_x in columns.start / TILE_SIZE..=last_x {
+                bins[tile_y * tiles_x + tile_x].push(i);
+            }
         }
     }
+    bins
+}
+
+fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, mesh: &IndexedMesh, program: Program, state: &RenderState) {
+    for_each_group(uniforms, mesh, program, state, |uniforms, triangles| {
+        let bins = bin_triangles(triangles, framebuffer.width, framebuffer.height);
+        let mut tiles = framebuffer.tiles();
+
+        // Rasterization and Fragment Processing Stage
+        // Tiles are shaded in parallel. Each pixel belongs to exactly one tile and every tile
+        // walks its triangles in order, so the result is the same as a single-threaded render.
+        // Fragments are streamed straight from the rasterizer to the depth test,
+        // so the fragment shader only runs on pixels that can still be visible.
+        tiles.par_iter_mut().zip(bins.par_iter()).for_each(|(tile, bin)| {
+            if state.polygon_mode.fills() {
+                for &i in bin {
+                    let [v1, v2, v3] = &triangles[i];
+                    triangle(v1, v2, v3, tile.columns.clone(), tile.rows.clone(), tile.sample_pattern, |coverage| {
+                        let (x, y) = (coverage.x, coverage.y);
+
+                        // Early stencil and depth test, per sample
+                        if state.early_depth_test && tile.depth_test(x, y, coverage.mask, &coverage.sample_depths, state) == 0 {
+                            tile.reject(x, y, coverage.mask, &coverage.sample_depths, state);
+                            return;
+                        }
+
+                        // Apply fragment shader, once per pixel. Depth-only passes skip it
+                        // unless the alpha test needs the shaded color.
+                        let shaded_color = if state.color_write || state.alpha_test.is_some() {
+                            let fragment = interpolate_fragment(v1, v2, v3, coverage, state.interpolation);
+                            program.color(&fragment, uniforms)
+                        } else {
+                            Color::black()
+                        };
+                        if state.alpha_test.is_some_and(|cutoff| shaded_color.alpha() < cutoff) {
+                            return;
+                        }
+                        tile.point(x, y, coverage.mask, &coverage.sample_depths, shaded_color, state);
+                    });
+                }
+            }
+
+            // Topology for debugging, drawn over whatever was filled
+            let wireframe_color = Color::from_hex(WIREFRAME_COLOR);
+            for &i in bin {
+                let [v1, v2, v3] = &triangles[i];
+                let (a, b, c) = (&v1.transformed_position, &v2.transformed_position, &v3.transformed_position);
+
+                match state.polygon_mode {
+                    PolygonMode::Line | PolygonMode::FillWireframe => {
+                        tile.line(a, b, wireframe_color, state);
+                        tile.line(b, c, wireframe_color, state);
+                        tile.line(c, a, wireframe_color, state);
+                    }
+                    PolygonMode::Point => {
+                        for position in [a, b, c] {
+                            tile.dot(position, POINT_SIZE, wireframe_color, state);
+                        }
+                    }
+                    PolygonMode::Fill => {}
+                }
+            }
+        });
+    });
+}
 
-    // Rasterization Stage
-    let mut fragments = Vec::new();
-    for tri in &triangles {
-        fragments.extend(triangle(&tri[0], &tri[1], &tri[2]));
+// Geometry pass of the deferred path: same front end as `render`, but instead of
+// a lit color every visible fragment stores its surface in the G-buffer, while
+// depth and stencil go to the framebuffer as usual
+fn render_gbuffer(framebuffer: &mut Framebuffer, gbuffer: &mut GBuffer, uniforms: &Uniforms, mesh: &IndexedMesh, program: Program, state: &RenderState) {
+    for_each_group(uniforms, mesh, program, state, |uniforms, triangles| {
+        let bins = bin_triangles(triangles, framebuffer.width, framebuffer.height);
+        let mut tiles = framebuffer.tiles();
+        let mut surfaces = gbuffer.tiles();
+
+        tiles.par_iter_mut().zip(surfaces.par_iter_mut()).zip(bins.par_iter()).for_each(|((tile, surfaces), bin)| {
+            for &i in bin {
+                let [v1, v2, v3] = &triangles[i];
+                triangle(v1, v2, v3, tile.columns.clone(), tile.rows.clone(), tile.sample_pattern, |coverage| {
+                    let (x, y) = (coverage.x, coverage.y);
+
+                    if state.early_depth_test && tile.depth_test(x, y, coverage.mask, &coverage.sample_depths, state) == 0 {
+                        tile.reject(x, y, coverage.mask, &coverage.sample_depths, state);
+                        return;
+                    }
+
+                    let fragment = interpolate_fragment(v1, v2, v3, coverage, state.interpolation);
+                    let surface = program.surface(&fragment, uniforms);
+                    let covered = tile.cover(x, y, coverage.mask, &coverage.sample_depths, state);
+                    surfaces.store(x, y, covered, &surface);
+                });
+            }
+        });
+    });
+}
+
+// Lo que comparten todos los objetos que se dibujan desde una misma cámara
+pub struct RenderContext<'a> {
+    pub camera: &'a Camera,
+    pub shaders: &'a ShaderRegistry,
+    pub time: u32,
+    // Texturas de las cámaras fuera de pantalla, por índice de render target
+    pub textures: &'a [Arc<Texture>],
+    // Luces de la escena y su luz ambiental
+    pub lights: &'a [Light],
+    pub ambient: Color,
+}
+
+fn create_uniforms<'a>(model_matrix: Mat4, framebuffer: &Framebuffer, context: &RenderContext<'a>, texture: Option<Arc<Texture>>) -> Uniforms<'a> {
+    let camera = context.camera;
+    let width = framebuffer.width as f32;
+    let height = framebuffer.height as f32;
+
+    Uniforms {
+        model_matrix,
+        view_matrix: create_view_matrix(camera.eye, camera.center, camera.up),
+        projection_matrix: create_perspective_matrix(width, height),
+        viewport_matrix: create_viewport_matrix(width, height),
+        time: context.time,
+        texture,
+        lights: context.lights,
+        ambient: context.ambient,
+        camera_position: camera.eye,
+        material: Material::default(),
     }
+}
 
-    // Fragment Processing Stage
-    for fragment in fragments {
-        let x = fragment.position.x as usize;
-        let y = fragment.position.y as usize;
-        if x < framebuffer.width && y < framebuffer.height {
-            // Apply fragment shader
-            let shaded_color = fragment_shader(&fragment, &uniforms, id);
-            let color = shaded_color.to_hex();
-            framebuffer.set_current_color(color);
-            framebuffer.point(x, y, fragment.depth);
-        }
+// Clears the framebuffer and draws the background as seen from the camera, if any.
+// The background writes no depth, so the objects drawn next always cover it.
+fn clear_with_background(framebuffer: &mut Framebuffer, background: Option<&Background>, camera: &Camera) {
+    framebuffer.clear();
+
+    if let Some(background) = background {
+        let view_matrix = create_view_matrix(camera.eye, camera.center, camera.up);
+        let projection_matrix = create_perspective_matrix(framebuffer.width as f32, framebuffer.height as f32);
+        background.draw(framebuffer, &view_matrix, &projection_matrix);
     }
 }
 
-pub struct SceneObject {
-    pub translation: Vec3,
-    pub rotation: Vec3,
-    pub scale: f32,
-    pub vertex_array: Vec<Vertex>,
-    pub id: f32, 
+// Renders the depth of the casters as seen with the given matrices
+fn render_depth_map(view_matrix: Mat4, projection_matrix: Mat4, casters: &[&SceneObject], shaders: &ShaderRegistry, time: u32, settings: &ShadowSettings) -> DepthMap {
+    let size = settings.resolution;
+    let mut framebuffer = Framebuffer::new(size, size);
+    framebuffer.clear();
+
+    for object in casters {
+        let uniforms = Uniforms {
+            model_matrix: object.model_matrix,
+            view_matrix,
+            projection_matrix,
+            viewport_matrix: create_viewport_matrix(size as f32, size as f32),
+            time,
+            texture: None,
+            lights: &[],
+            ambient: Color::black(),
+            camera_position: Vec3::zeros(),
+            material: Material::default(),
+        };
+        // Only depth; translucent surfaces cast shadow where they are opaque enough
+        let state = RenderState {
+            color_write: false,
+            alpha_test: object.render_state.is_transparent().then_some(0.3),
+            ..RenderState::double_sided()
+        };
+
+        let program = shaders.program(object.vertex_shader, object.fragment_shader);
+        render(&mut framebuffer, &uniforms, &object.mesh, program, &state);
+    }
+
+    DepthMap::from_framebuffer(&framebuffer, view_matrix, projection_matrix)
 }
 
+// Shadow map of a light, fitted to the bounding sphere of the casters. Point and spot
+// lights get one depth map per cube face so they cast shadows in every direction.
+fn render_shadow_map(light: &Light, casters: &[&SceneObject], shaders: &ShaderRegistry, time: u32, settings: &ShadowSettings) -> ShadowMap {
+    let center = casters.iter().map(|object| object.position()).sum::<Vec3>() / casters.len() as f32;
+    let radius = casters
+        .iter()
+        .map(|object| (object.position() - center).magnitude() + object.bounding_radius())
+        .fold(0.0, f32::max);
+
+    match light.kind {
+        LightKind::Directional { direction } => {
+            let (view, projection) = directional_light_matrices(&direction, &center, radius);
+            ShadowMap::Directional(render_depth_map(view, projection, casters, shaders, time, settings), *settings)
+        }
+        LightKind::Point { position, .. } | LightKind::Spot { position, .. } => {
+            let far = (center - position).magnitude() + radius;
+            let (views, projection) = point_light_matrices(&position, far);
+            // Each face only covers 90 degrees, so half the resolution keeps a similar texel size
+            let face_settings = ShadowSettings {
+                resolution: settings.resolution / 2,
+                ..*settings
+            };
+            let faces = views
+                .iter()
+                .map(|&view| render_depth_map(view, projection, casters, shaders, time, &face_settings))
+                .collect();
+            ShadowMap::Point(position, faces, *settings)
+        }
+    }
+}
 
+// Dibuja los objetos vistos desde la cámara del contexto: primero los opacos y luego
+// los transparentes de atrás hacia adelante, compartiendo el mismo depth buffer.
+// Con un G-buffer los opacos van por el camino diferido y se iluminan todos juntos
+// antes de dibujar los transparentes.
+fn render_objects<F>(framebuffer: &mut Framebuffer, mut gbuffer: Option<&mut GBuffer>, objects: &[&SceneObject], context: &RenderContext, state_for: F)
+where
+    F: Fn(&SceneObject) -> RenderState,
+{
+    let eye = context.camera.eye;
+    let (opaque, mut transparent): (Vec<&SceneObject>, Vec<&SceneObject>) =
+        objects.iter().partition(|object| !object.render_state.is_transparent());
+    transparent.sort_by(|a, b| {
+        let distance_a = (a.position() - eye).magnitude();
+        let distance_b = (b.position() - eye).magnitude();
+        distance_b.total_cmp(&distance_a)
+    });
+
+    let program_for = |object: &SceneObject| context.shaders.program(object.vertex_shader, object.fragment_shader);
+    let uniforms_for = |object: &SceneObject, framebuffer: &Framebuffer| {
+        let texture = object.render_target.and_then(|i| context.textures.get(i)).cloned();
+        create_uniforms(object.model_matrix, framebuffer, context, texture)
+    };
+
+    if let Some(gbuffer) = gbuffer.as_deref_mut() {
+        gbuffer.clear();
+        for &object in &opaque {
+            let state = state_for(object);
+            if state.polygon_mode.fills() {
+                render_gbuffer(framebuffer, gbuffer, &uniforms_for(object, framebuffer), &object.mesh, program_for(object), &state);
+            }
+        }
+        gbuffer.lighting_pass(framebuffer, context.lights, context.ambient, &context.camera.eye);
+    }
 
-fn main() {
+    for object in opaque.into_iter().chain(transparent) {
+        let state = state_for(object);
 
-    let obj = Obj::load("assets/sphere.obj").expect("Failed to load obj");
-    let obj2 = Obj::load("assets/rings.obj").expect("Failed to load obj");
-
-    let objects = vec![
-        
-        // planeta con luna
-        SceneObject {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: 1.0,
-            vertex_array: obj.get_vertex_array(),
-            id: 1.0,
-        },
-                // luna
-        SceneObject {
-            translation: Vec3::new(0.5, 1.0, 0.0),
-            rotation: Vec3::new(0.0, PI / 4.0, 0.0),
-            scale: 0.3,
-            vertex_array: obj.get_vertex_array(), // Reutilizando el mismo modelo
-            id: 2.0,
-        },
+        // Ya dibujado en el G-buffer; las líneas y puntos de depuración se agregan encima
+        // y el relleno repetido no pasa la prueba de profundidad
+        if gbuffer.is_some() && !object.render_state.is_transparent() && state.polygon_mode == PolygonMode::Fill {
+            continue;
+        }
 
-        SceneObject {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: 1.0,
-            vertex_array: obj.get_vertex_array(),
-            id: 3.0,
-        },SceneObject {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: 1.0,
-            vertex_array: obj.get_vertex_array(),
-            id: 4.0,
-        },SceneObject {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: 1.0,
-            vertex_array: obj.get_vertex_array(),
-            id: 5.0,
-        },SceneObject {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: 1.0,
-            vertex_array: obj.get_vertex_array(),
-            id: 6.0,
-        },SceneObject {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: 1.0,
-            vertex_array: obj.get_vertex_array(),
-            id: 7.0,
-        },
-        
-        
-       
-    ];
+        render(framebuffer, &uniforms_for(object, framebuffer), &object.mesh, program_for(object), &state);
+    }
+}
 
+// Teclas de los cuerpos de la escena, en el orden en que se declaran
+const BODY_KEYS: [Key; 9] = [
+    Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+];
 
+fn main() {
+
+    // Escena de `--scene <ruta>`, o la escena por defecto
+    let mut scene = scene_from_args();
+    let outline = scene.shaders.resolve_fragment("outline").expect("Falta el shader del contorno");
 
     let window_width = 800;
     let window_height = 600;
     let framebuffer_width = 800;
     let framebuffer_height = 600;
 
-    let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
+    let samples = msaa_samples_from_args();
+    let mut framebuffer = Framebuffer::with_samples(framebuffer_width, framebuffer_height, samples);
     let mut window = Window::new(
         "Shaders",
         window_width,
@@ -219,20 +477,26 @@ fn main() {
 
     framebuffer.set_background_color(0x333355);
 
-    // model position
-    let translation = Vec3::new(0.0, 0.0, 0.0);
-    let rotation = Vec3::new(0.0, 0.0, 0.0);
-    let scale = 1.0f32;
+    let shadow_settings = ShadowSettings::default();
+    let mut shadows = true;
+    let mut gbuffer = GBuffer::new(framebuffer_width, framebuffer_height, samples);
+    let mut deferred = false;
 
-    // camera parameters
-    let mut camera = Camera::new(
-        Vec3::new(0.0, 0.0, 5.0),
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(0.0, 1.0, 0.0)
-    );
+    // Cámaras fuera de pantalla: cada una dibuja un grupo de objetos en su propia textura
+    for (target, _) in scene.render_targets.iter_mut() {
+        target.framebuffer.set_background_color(0x000010);
+    }
+
+    // Fondo de la escena, o la imagen indicada con `--background`
+    let background = background_from_args().or(scene.background.take());
+    let mut show_background = true;
 
     let mut time = 0;
-    let mut selected_object = 0; // Índice inicial del objeto seleccionado
+    // Cuerpo que sigue la cámara y dónde estaba en el cuadro anterior; al inicio se ve toda la escena
+    let mut focus: Option<(usize, Vec3)> = None;
+    let mut interpolation = Interpolation::Perspective;
+    let mut polygon_mode = PolygonMode::Fill;
+    let mut show_outline = false;
 
     while window.is_open() {
         if window.is_key_down(Key::Escape) {
@@ -240,47 +504,122 @@ fn main() {
         }
     
         time += 1;
-    
-        handle_input(&window, &mut camera);
-    
-        // Detectar teclas del 1 al 7 para cambiar el objeto seleccionado
-        if window.is_key_down(Key::Key1) {
-            selected_object = 0; // Objeto 1
-        } else if window.is_key_down(Key::Key2) {
-            selected_object = 1; // Objeto 2
-        } else if window.is_key_down(Key::Key3) {
-            selected_object = 2; // Objeto 3
-        } else if window.is_key_down(Key::Key4) {
-            selected_object = 3; // Objeto 4
-        } else if window.is_key_down(Key::Key5) {
-            selected_object = 4; // Objeto 5
-        } else if window.is_key_down(Key::Key6) {
-            selected_object = 5; // Objeto 6
-        } else if window.is_key_down(Key::Key7) {
-            selected_object = 6; // Objeto 7
+
+        // Las lunas giran alrededor de sus planetas y todo hijo sigue a su padre
+        scene.update(time);
+
+        // La cámara acompaña al cuerpo enfocado cuando este se mueve
+        if let Some((body, last_center)) = focus.as_mut() {
+            let (center, _) = scene.body_bounds(*body);
+            scene.camera.translate(center - *last_center);
+            *last_center = center;
         }
     
-        framebuffer.clear();
+        handle_input(&window, &mut scene.camera);
+
+        // Alternar entre interpolación con perspectiva y afín para compararlas
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            interpolation = interpolation.toggle();
+        }
+
+        // Cambiar entre relleno, líneas, puntos y relleno con malla
+        if window.is_key_pressed(Key::M, KeyRepeat::No) {
+            polygon_mode = polygon_mode.next();
+        }
+
+        // Camino diferido con G-buffer y, dentro de él, las vistas de depuración del G-buffer
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            deferred = !deferred;
+        }
+        if window.is_key_pressed(Key::V, KeyRepeat::No) {
+            gbuffer.output = gbuffer.output.next();
+        }
+
+        // Sombras de todas las luces
+        if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            shadows = !shadows;
+        }
+
+        // Fondo del espacio o color plano
+        if window.is_key_pressed(Key::B, KeyRepeat::No) {
+            show_background = !show_background;
+        }
+
+        // Contorno de selección alrededor del cuerpo enfocado
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            show_outline = !show_outline;
+        }
     
-        let view_matrix = create_view_matrix(camera.eye, camera.center, camera.up);
-        let projection_matrix = create_perspective_matrix(window_width as f32, window_height as f32);
-        let viewport_matrix = create_viewport_matrix(framebuffer_width as f32, framebuffer_height as f32);
+        // Las teclas del 1 al 9 enfocan la cámara en un cuerpo de la escena
+        for (i, &key) in BODY_KEYS.iter().enumerate().take(scene.bodies.len()) {
+            if window.is_key_pressed(key, KeyRepeat::No) {
+                let (center, radius) = scene.body_bounds(i);
+                scene.camera.focus(center, radius * 3.0);
+                focus = Some((i, center));
+            }
+        }
     
-        // Renderizar solo el objeto seleccionado
-        if let Some(object) = objects.get(selected_object) {
-            let model_matrix = create_model_matrix(object.translation, object.scale, object.rotation);
-            let uniforms = Uniforms {
-                model_matrix,
-                view_matrix,
-                projection_matrix,
-                viewport_matrix,
-                time,
+        // Primero se actualizan las texturas de las cámaras fuera de pantalla, sin sombras
+        let mut textures = Vec::with_capacity(scene.render_targets.len());
+        for (target, indices) in scene.render_targets.iter_mut() {
+            let visible: Vec<&SceneObject> = indices.iter().filter_map(|&i| scene.objects.get(i)).collect();
+            let context = RenderContext { camera: &target.camera, shaders: &scene.shaders, time, textures: &textures, lights: &scene.lights, ambient: scene.ambient };
+
+            clear_with_background(&mut target.framebuffer, background.as_ref().filter(|_| show_background), &target.camera);
+            render_objects(&mut target.framebuffer, None, &visible, &context, |object| object.render_state);
+            target.update_texture();
+            textures.push(target.texture());
+        }
+
+        // Toda la escena se dibuja en el mismo cuadro, compartiendo el depth buffer
+        let visible: Vec<&SceneObject> = scene.objects.iter().collect();
+
+        // Los objetos proyectan sombra unos sobre otros, salvo los que llevan una luz dentro
+        let casters: Vec<&SceneObject> = scene.objects.iter().filter(|object| object.casts_shadows).collect();
+        let mut scene_lights = scene.lights.to_vec();
+        if shadows && !casters.is_empty() {
+            for light in scene_lights.iter_mut().filter(|light| light.casts_shadows) {
+                light.shadow = Some(Arc::new(render_shadow_map(light, &casters, &scene.shaders, time, &shadow_settings)));
+            }
+        }
+        let context = RenderContext { camera: &scene.camera, shaders: &scene.shaders, time, textures: &textures, lights: &scene_lights, ambient: scene.ambient };
+        clear_with_background(&mut framebuffer, background.as_ref().filter(|_| show_background), &scene.camera);
+
+        // El primer objeto del cuerpo enfocado es el que lleva el contorno
+        let active = focus.map(|(body, _)| &scene.objects[scene.bodies[body][0]]);
+
+        let gbuffer = if deferred { Some(&mut gbuffer) } else { None };
+        render_objects(&mut framebuffer, gbuffer, &visible, &context, |object| {
+            let mut state = RenderState {
+                interpolation,
+                polygon_mode,
+                ..object.render_state
             };
-    
-            framebuffer.set_current_color(0xFFDDDD); // Color del objeto
-            render(&mut framebuffer, &uniforms, &object.vertex_array, object.id);
+
+            // Marca en el stencil los píxeles que cubre el cuerpo enfocado
+            if show_outline && active.is_some_and(|active| std::ptr::eq(object, active)) {
+                state.stencil = StencilState::write(1);
+            }
+
+            state
+        });
+
+        // El contorno es una copia un poco más grande del cuerpo, dibujada solo fuera de él
+        if let Some(active) = active.filter(|_| show_outline) {
+            let model_matrix = active.model_matrix * create_model_matrix(Vec3::zeros(), 1.06, Vec3::zeros());
+            let uniforms = create_uniforms(model_matrix, &framebuffer, &context, None);
+            let state = RenderState {
+                interpolation,
+                stencil: StencilState::test(CompareFunc::NotEqual, 1),
+                ..RenderState::default()
+            };
+
+            let program = scene.shaders.program(active.vertex_shader, outline);
+            render(&mut framebuffer, &uniforms, &active.mesh, program, &state);
         }
     
+        framebuffer.resolve();
+
         window
             .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
             .unwrap();
@@ -290,6 +629,61 @@ fn main() {
     
 }
 
+// Muestras por píxel para MSAA, elegidas al iniciar con `--msaa <1|2|4|8>`
+fn msaa_samples_from_args() -> usize {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.iter().position(|arg| arg == "--msaa") {
+        Some(i) => match args.get(i + 1).and_then(|value| value.parse().ok()) {
+            Some(samples @ (1 | 2 | 4 | 8)) => samples,
+            _ => {
+                eprintln!("--msaa espera 1, 2, 4 u 8 muestras");
+                std::process::exit(1);
+            }
+        },
+        None => 1,
+    }
+}
+
+// Escena elegida al iniciar con `--scene <ruta>`; sin él, la del sistema solar
+fn scene_from_args() -> Scene {
+    let args: Vec<String> = std::env::args().collect();
+
+    let path = match args.iter().position(|arg| arg == "--scene") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => path.as_str(),
+            None => {
+                eprintln!("--scene espera la ruta de un archivo de escena");
+                std::process::exit(1);
+            }
+        },
+        None => DEFAULT_SCENE,
+    };
+
+    Scene::load(path).unwrap_or_else(|error| {
+        eprintln!("No se pudo cargar la escena {}: {}", path, error);
+        std::process::exit(1);
+    })
+}
+
+const DEFAULT_SCENE: &str = "assets/scenes/solar.scene";
+
+// Fondo elegido al iniciar con `--background <ruta>`, en lugar del de la escena: una carpeta
+// con las seis caras de un cubemap (px, nx, py, ny, pz, nz) o una imagen equirectangular
+fn background_from_args() -> Option<Background> {
+    let args: Vec<String> = std::env::args().collect();
+
+    let i = args.iter().position(|arg| arg == "--background")?;
+    let Some(path) = args.get(i + 1) else {
+        eprintln!("--background espera una imagen o una carpeta con un cubemap");
+        std::process::exit(1);
+    };
+    Some(Background::load(path).unwrap_or_else(|error| {
+        eprintln!("No se pudo cargar el fondo {}: {}", path, error);
+        std::process::exit(1);
+    }))
+}
+
 fn handle_input(window: &Window, camera: &mut Camera) {
     let movement_speed = 1.0;
     let rotation_speed = PI/50.0;
@@ -337,3 +731,159 @@ fn handle_input(window: &Window, camera: &mut Camera) {
       camera.zoom(-zoom_speed);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use fragment::Fragment;
+    use shader::FragmentShader;
+    use shaders::StandardVertex;
+    use surface::Surface;
+
+    // Counts how many fragments it shades
+    struct Counting(Arc<AtomicUsize>);
+
+    impl FragmentShader for Counting {
+        fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Surface::emissive(fragment, Color::new(255, 255, 255))
+        }
+    }
+
+    // Draws a quad and then a second one hidden behind it. Returns the image and
+    // the number of fragments shaded.
+    fn draw_occluded_quads(state: RenderState) -> (Framebuffer, usize) {
+        let shaded = Arc::new(AtomicUsize::new(0));
+        let mut shaders = ShaderRegistry::default();
+        let vertex = shaders.register_vertex("standard", Box::new(StandardVertex));
+        let fragment = shaders.register_fragment("counting", Box::new(Counting(Arc::clone(&shaded)))).unwrap();
+
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 3.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        let context = RenderContext { camera: &camera, shaders: &shaders, time: 0, textures: &[], lights: &[], ambient: Color::black() };
+        let mesh = IndexedMesh::quad(1.0, 1.0);
+        let mut framebuffer = Framebuffer::new(32, 32);
+        framebuffer.clear();
+
+        for z in [0.0, -1.0] {
+            let uniforms = create_uniforms(create_model_matrix(Vec3::new(0.0, 0.0, z), 1.0, Vec3::zeros()), &framebuffer, &context, None);
+            render(&mut framebuffer, &uniforms, &mesh, shaders.program(vertex, fragment), &state);
+        }
+
+        framebuffer.resolve();
+        let shaded = shaded.load(Ordering::Relaxed);
+        (framebuffer, shaded)
+    }
+
+    #[test]
+    fn late_z_shades_hidden_fragments() {
+        let (early, early_shaded) = draw_occluded_quads(RenderState::default());
+        let (late, late_shaded) = draw_occluded_quads(RenderState { early_depth_test: false, ..RenderState::default() });
+
+        // Same image, but only late-Z runs the shader on the quad behind
+        assert!(early_shaded > 0);
+        assert!(late_shaded > early_shaded, "{} <= {}", late_shaded, early_shaded);
+        assert_eq!(early.buffer, late.buffer);
+        assert_eq!(early.depths(), late.depths());
+    }
+
+    // Surface of a single emissive color
+    struct Flat(Color);
+
+    impl FragmentShader for Flat {
+        fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+            Surface::emissive(fragment, self.0)
+        }
+    }
+
+    // Two overlapping quads of different colors with 4x MSAA, forward or deferred
+    fn draw_overlapping_quads(deferred: bool) -> Framebuffer {
+        let mut shaders = ShaderRegistry::default();
+        let vertex = shaders.register_vertex("standard", Box::new(StandardVertex));
+        let red = shaders.register_fragment("red", Box::new(Flat(Color::new(255, 0, 0)))).unwrap();
+        let blue = shaders.register_fragment("blue", Box::new(Flat(Color::new(0, 0, 255)))).unwrap();
+
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 3.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        let context = RenderContext { camera: &camera, shaders: &shaders, time: 0, textures: &[], lights: &[], ambient: Color::black() };
+        let mesh = IndexedMesh::quad(1.0, 1.0);
+        let mut framebuffer = Framebuffer::with_samples(32, 32, 4);
+        let mut gbuffer = GBuffer::new(32, 32, 4);
+        framebuffer.clear();
+        gbuffer.clear();
+
+        let quads = [(Vec3::new(-0.2, 0.0, 0.0), 0.5, red), (Vec3::new(0.3, 0.1, -0.2), -0.3, blue)];
+        for (position, angle, fragment) in quads {
+            let uniforms = create_uniforms(create_model_matrix(position, 1.0, Vec3::new(0.0, 0.0, angle)), &framebuffer, &context, None);
+            let program = shaders.program(vertex, fragment);
+            if deferred {
+                render_gbuffer(&mut framebuffer, &mut gbuffer, &uniforms, &mesh, program, &RenderState::default());
+            } else {
+                render(&mut framebuffer, &uniforms, &mesh, program, &RenderState::default());
+            }
+        }
+        if deferred {
+            gbuffer.lighting_pass(&mut framebuffer, &[], Color::black(), &camera.eye);
+        }
+
+        framebuffer.resolve();
+        framebuffer
+    }
+
+    #[test]
+    fn deferred_keeps_msaa_edges_between_objects() {
+        let forward = draw_overlapping_quads(false);
+        let deferred = draw_overlapping_quads(true);
+
+        // Where the quads meet, pixels mix red and blue samples
+        assert!(forward.buffer.iter().any(|&pixel| pixel & 0xFF0000 != 0 && pixel & 0xFF != 0));
+        assert_eq!(forward.buffer, deferred.buffer);
+    }
+
+    // Renders the default scene with the given number of worker threads
+    fn render_with_threads(threads: usize) -> Framebuffer {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        pool.install(|| {
+            let mut scene = Scene::load(DEFAULT_SCENE).unwrap();
+            scene.update(10);
+            let visible: Vec<&SceneObject> = scene.objects.iter().collect();
+            let context = RenderContext { camera: &scene.camera, shaders: &scene.shaders, time: 10, textures: &[], lights: &scene.lights, ambient: scene.ambient };
+            let mut framebuffer = Framebuffer::with_samples(160, 120, 4);
+            framebuffer.clear();
+            render_objects(&mut framebuffer, None, &visible, &context, |object| object.render_state);
+            framebuffer.resolve();
+            framebuffer
+        })
+    }
+
+    #[test]
+    fn tiles_render_the_same_on_any_number_of_threads() {
+        let single = render_with_threads(1);
+        let parallel = render_with_threads(4);
+
+        assert_eq!(single.buffer, parallel.buffer);
+        assert_eq!(single.depths(), parallel.depths());
+    }
+
+    #[test]
+    fn a_moon_shadows_its_planet_from_a_point_light() {
+        // The sun at the origin, a planet at x = 5 and a moon-sized sphere right between them
+        let source = "mesh sphere assets/sphere.obj\n\
+            light point position 0 0 0 range 12\n\
+            object planet sphere gas_giant position 5 0 0 scale 1\n\
+            object moon sphere moon position 3.5 0 0 scale 0.3\n";
+        let scene = Scene::parse(source).unwrap();
+        let casters: Vec<&SceneObject> = scene.objects.iter().collect();
+        let shadow = render_shadow_map(&scene.lights[0], &casters, &scene.shaders, 0, &ShadowSettings::default());
+
+        // The point of the planet facing the sun is behind the moon
+        let toward_sun = Vec3::new(-1.0, 0.0, 0.0);
+        assert!(shadow.visibility(&Vec3::new(4.5, 0.0, 0.0), &toward_sun) < 1.0);
+
+        // Further up the sun-facing side the light misses the moon and the bias keeps off acne
+        for angle in [40.0f32, 60.0, 80.0] {
+            let normal = Vec3::new(-angle.to_radians().cos(), angle.to_radians().sin(), 0.0);
+            let point = Vec3::new(5.0, 0.0, 0.0) + normal * 0.5;
+            assert_eq!(shadow.visibility(&point, &normal), 1.0, "{} degrees", angle);
+        }
+    }
+}
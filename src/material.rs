@@ -0,0 +1,51 @@
+use crate::color::Color;
+
+// Surface parameters of a face group, as authored in an MTL file
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    // Ka, reflectance for the ambient light
+    pub ambient: Color,
+    // Kd
+    pub diffuse: Color,
+    // Ks
+    pub specular: Color,
+    // Ns, the Blinn-Phong exponent
+    pub shininess: f32,
+    // Ni, index of refraction; drives the Fresnel reflection at grazing angles
+    pub optical_density: f32,
+}
+
+impl Material {
+    pub fn from_mtl(material: &tobj::Material) -> Self {
+        let default = Material::default();
+        let color = |value: Option<[f32; 3]>, fallback: Color| {
+            value.map_or(fallback, |[r, g, b]| Color::from_float(r, g, b))
+        };
+
+        Material {
+            ambient: color(material.ambient, default.ambient),
+            diffuse: color(material.diffuse, default.diffuse),
+            specular: color(material.specular, default.specular),
+            shininess: material.shininess.unwrap_or(default.shininess),
+            optical_density: material.optical_density.unwrap_or(default.optical_density),
+        }
+    }
+
+    // Reflectance at normal incidence, from the index of refraction (Schlick)
+    pub fn base_reflectance(&self) -> f32 {
+        ((self.optical_density - 1.0) / (self.optical_density + 1.0)).powi(2)
+    }
+}
+
+// Plain diffuse gray, used by meshes without an MTL file
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            ambient: Color::new(255, 255, 255),
+            diffuse: Color::new(204, 204, 204),
+            specular: Color::black(),
+            shininess: 1.0,
+            optical_density: 1.0,
+        }
+    }
+}
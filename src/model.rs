@@ -1,6 +1,7 @@
-use tobj;
+use std::ops::Range;
 use nalgebra_glm::{Vec2, Vec3};
 use crate::vertex::Vertex;
+use crate::material::Material;
 
 pub struct Obj {
     meshes: Vec<Mesh>,
@@ -11,18 +12,30 @@ struct Mesh {
     normals: Vec<Vec3>,
     texcoords: Vec<Vec2>,
     indices: Vec<u32>,
+    material: Material,
 }
 
 impl Obj {
     pub fn load(filename: &str) -> Result<Self, tobj::LoadError> {
-        let (models, _) = tobj::load_obj(filename, &tobj::LoadOptions {
+        let (models, materials) = tobj::load_obj(filename, &tobj::LoadOptions {
             single_index: true,
             triangulate: true,
             ..Default::default()
         })?;
 
+        // A missing or broken MTL file leaves every mesh with the default material
+        let materials: Vec<Material> = materials
+            .map(|materials| materials.iter().map(Material::from_mtl).collect())
+            .unwrap_or_default();
+
+        // tobj starts a new model for every object, group and material change,
+        // so each model is one face group with a single material
         let meshes = models.into_iter().map(|model| {
             let mesh = model.mesh;
+            let material = mesh.material_id
+                .and_then(|id| materials.get(id))
+                .copied()
+                .unwrap_or_default();
             Mesh {
                 vertices: mesh.positions.chunks(3)
                     .map(|v| Vec3::new(v[0], v[1], v[2]))
@@ -34,29 +47,139 @@ impl Obj {
                     .map(|t| Vec2::new(t[0], 1.0 - t[1]))
                     .collect(),
                 indices: mesh.indices,
+                material,
             }
         }).collect();
 
         Ok(Obj { meshes })
     }
 
-    pub fn get_vertex_array(&self) -> Vec<Vertex> {
+    // Keeps the index buffer, so every vertex shared between triangles is stored once
+    pub fn get_indexed_mesh(&self) -> IndexedMesh {
         let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut groups = Vec::new();
 
         for mesh in &self.meshes {
-            for &index in &mesh.indices {
-                let position = mesh.vertices[index as usize];
-                let normal = mesh.normals.get(index as usize)
+            let base = vertices.len() as u32;
+
+            for (i, &position) in mesh.vertices.iter().enumerate() {
+                let normal = mesh.normals.get(i)
                     .cloned()
                     .unwrap_or(Vec3::new(0.0, 1.0, 0.0));
-                let tex_coords = mesh.texcoords.get(index as usize)
+                let tex_coords = mesh.texcoords.get(i)
                     .cloned()
                     .unwrap_or(Vec2::new(0.0, 0.0));
 
                 vertices.push(Vertex::new(position, normal, tex_coords));
             }
+
+            let start = indices.len();
+            indices.extend(mesh.indices.iter().map(|&index| base + index));
+            groups.push(FaceGroup {
+                indices: start..indices.len(),
+                material: mesh.material,
+            });
+        }
+
+        compute_tangents(&mut vertices, &indices);
+        IndexedMesh { vertices, indices, groups }
+    }
+}
+
+// Per-vertex tangents from the texture coordinates: every triangle adds the
+// directions in which u and v grow across it to its three vertices, and the sums
+// are made orthogonal to the vertex normal. Vertices without usable texture
+// coordinates get an arbitrary frame around their normal.
+fn compute_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vec3::zeros(); vertices.len()];
+    let mut bitangents = vec![Vec3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [i1, i2, i3] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let (v1, v2, v3) = (&vertices[i1], &vertices[i2], &vertices[i3]);
+
+        let edge1 = v2.position - v1.position;
+        let edge2 = v3.position - v1.position;
+        let duv1 = v2.tex_coords - v1.tex_coords;
+        let duv2 = v3.tex_coords - v1.tex_coords;
+
+        let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+        for i in [i1, i2, i3] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = vertex.normal.normalize();
+
+        // Gram-Schmidt against the normal
+        let mut tangent = tangent - normal * normal.dot(&tangent);
+        if tangent.magnitude() <= f32::EPSILON {
+            let axis = if normal.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+            tangent = axis - normal * normal.dot(&axis);
         }
+        let tangent = tangent.normalize();
+
+        // The bitangent keeps the handedness of the texture mapping
+        let handedness = if normal.cross(&tangent).dot(&bitangent) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = tangent;
+        vertex.bitangent = normal.cross(&tangent) * handedness;
+    }
+}
+
+// Unique vertices plus the triangle list that references them, split into
+// face groups that each have their own material
+pub struct IndexedMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub groups: Vec<FaceGroup>,
+}
+
+// A range of the index buffer drawn with one material
+pub struct FaceGroup {
+    pub indices: Range<usize>,
+    pub material: Material,
+}
+
+impl IndexedMesh {
+    // Rectangle centered on the origin facing +z, with UVs covering [0, 1]
+    pub fn quad(width: f32, height: f32) -> Self {
+        let (w, h) = (width / 2.0, height / 2.0);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+
+        let mut vertices = vec![
+            Vertex::new(Vec3::new(-w, -h, 0.0), normal, Vec2::new(0.0, 1.0)),
+            Vertex::new(Vec3::new(w, -h, 0.0), normal, Vec2::new(1.0, 1.0)),
+            Vertex::new(Vec3::new(w, h, 0.0), normal, Vec2::new(1.0, 0.0)),
+            Vertex::new(Vec3::new(-w, h, 0.0), normal, Vec2::new(0.0, 0.0)),
+        ];
+
+        let indices = vec![0, 1, 2, 0, 2, 3];
+        compute_tangents(&mut vertices, &indices);
+
+        IndexedMesh {
+            vertices,
+            indices,
+            groups: vec![FaceGroup {
+                indices: 0..6,
+                material: Material::default(),
+            }],
+        }
+    }
 
-        vertices
+    // Distance from the origin to the farthest vertex
+    pub fn bounding_radius(&self) -> f32 {
+        self.vertices
+            .iter()
+            .map(|vertex| vertex.position.magnitude())
+            .fold(0.0, f32::max)
     }
 }
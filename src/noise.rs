@@ -0,0 +1,146 @@
+use fastnoise_lite::{CellularDistanceFunction, DomainWarpType, FastNoiseLite, FractalType, NoiseType};
+use nalgebra_glm::Vec3;
+
+pub use fastnoise_lite::CellularReturnType;
+
+// The basis function that fractals are built from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    // Gradient noise on a cubic lattice
+    Perlin,
+    // Gradient noise on a simplex lattice, with fewer directional artifacts than Perlin
+    Simplex,
+    // Distance to the closest of a set of jittered feature points, one per cell
+    Cellular,
+    // Interpolated random values on a cubic lattice, blockier than gradient noise
+    Value,
+}
+
+// Seeded 3D noise for shaders, evaluated at object-space positions so the pattern
+// sticks to the mesh. Built like a sampler: a basis, then optionally a fractal and
+// domain warping on top.
+pub struct Noise {
+    noise: FastNoiseLite,
+    // Displaces the position before the noise is evaluated
+    warp: Option<FastNoiseLite>,
+}
+
+impl Noise {
+    // One unit of object space per feature, until `frequency` says otherwise
+    pub fn new(kind: NoiseKind, seed: i32) -> Self {
+        let mut noise = FastNoiseLite::with_seed(seed);
+        noise.set_frequency(Some(1.0));
+        noise.set_noise_type(Some(match kind {
+            NoiseKind::Perlin => NoiseType::Perlin,
+            NoiseKind::Simplex => NoiseType::OpenSimplex2,
+            NoiseKind::Cellular => NoiseType::Cellular,
+            NoiseKind::Value => NoiseType::Value,
+        }));
+        if kind == NoiseKind::Cellular {
+            noise.set_cellular_distance_function(Some(CellularDistanceFunction::Euclidean));
+        }
+
+        Noise { noise, warp: None }
+    }
+
+    pub fn frequency(mut self, frequency: f32) -> Self {
+        self.noise.set_frequency(Some(frequency));
+        self
+    }
+
+    // Fractal Brownian motion: octaves at twice the frequency and half the amplitude of the previous one
+    pub fn fbm(mut self, octaves: i32) -> Self {
+        self.noise.set_fractal_type(Some(FractalType::FBm));
+        self.noise.set_fractal_octaves(Some(octaves));
+        self
+    }
+
+    // Ridged multifractal: octaves folded around zero, so valleys of the basis become sharp crests
+    pub fn ridged(mut self, octaves: i32) -> Self {
+        self.noise.set_fractal_type(Some(FractalType::Ridged));
+        self.noise.set_fractal_octaves(Some(octaves));
+        self
+    }
+
+    // What a cellular noise returns: the distance to the closest point by default,
+    // or a random value per cell, among others
+    pub fn cellular_return(mut self, return_type: CellularReturnType) -> Self {
+        self.noise.set_cellular_return_type(Some(return_type));
+        self
+    }
+
+    // Domain warping: positions are pushed around by up to `amplitude` units with a
+    // simplex field of the given frequency, which swirls the pattern
+    pub fn warp(mut self, amplitude: f32, frequency: f32) -> Self {
+        let mut warp = FastNoiseLite::with_seed(self.noise.seed.wrapping_add(1));
+        warp.set_domain_warp_type(Some(DomainWarpType::OpenSimplex2));
+        warp.set_domain_warp_amp(Some(amplitude));
+        warp.set_frequency(Some(frequency));
+        self.warp = Some(warp);
+        self
+    }
+
+    // Noise at a position, roughly in [-1, 1]
+    pub fn get(&self, position: &Vec3) -> f32 {
+        let (x, y, z) = match &self.warp {
+            Some(warp) => warp.domain_warp_3d(position.x, position.y, position.z),
+            None => (position.x, position.y, position.z),
+        };
+
+        self.noise.get_noise_3d(x, y, z)
+    }
+
+    // Noise at a position remapped to [0, 1]
+    pub fn get01(&self, position: &Vec3) -> f32 {
+        (self.get(position) * 0.5 + 0.5).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One of each kind of noise the shaders build
+    fn noises(seed: i32) -> Vec<Noise> {
+        vec![
+            Noise::new(NoiseKind::Perlin, seed).frequency(2.0).fbm(4),
+            Noise::new(NoiseKind::Simplex, seed).frequency(4.0).fbm(5).warp(0.2, 1.5),
+            Noise::new(NoiseKind::Cellular, seed).frequency(3.0).cellular_return(CellularReturnType::CellValue),
+            Noise::new(NoiseKind::Value, seed).frequency(2.0).ridged(3),
+        ]
+    }
+
+    fn grid() -> impl Iterator<Item = Vec3> {
+        (0..1000).map(|i| Vec3::new((i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32) * 0.37 - Vec3::repeat(1.7))
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_noise() {
+        for (first, second) in noises(7).iter().zip(noises(7).iter()) {
+            assert!(grid().all(|position| first.get(&position) == second.get(&position)));
+        }
+
+        let (seven, eight) = (&noises(7)[1], &noises(8)[1]);
+        assert!(grid().any(|position| seven.get(&position) != eight.get(&position)));
+    }
+
+    #[test]
+    fn values_stay_in_the_range_shaders_expect() {
+        // `get01` is used directly as a blend factor, and thresholds like 0.45 assume
+        // the values spread over most of the range
+        for noise in noises(3) {
+            let values: Vec<f32> = grid().map(|position| noise.get01(&position)).collect();
+            assert!(values.iter().all(|value| (0.0..=1.0).contains(value)));
+
+            let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            assert!(min < 0.35 && max > 0.65, "{} to {}", min, max);
+        }
+
+        // Cellular distances start at -1, so the crater shaders get them back with `get + 1`
+        for return_type in [CellularReturnType::Distance, CellularReturnType::Distance2Sub] {
+            let noise = Noise::new(NoiseKind::Cellular, 3).frequency(3.0).cellular_return(return_type);
+            assert!(grid().all(|position| noise.get(&position) >= -1.0));
+        }
+    }
+}
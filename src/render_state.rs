@@ -0,0 +1,329 @@
+use crate::color::Color;
+use crate::triangle::Interpolation;
+
+// Which faces are discarded before rasterization
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+// Winding order, as seen on screen, that counts as a front face
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+// How the rasterized triangles are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+    // Filled triangles with their edges drawn on top
+    FillWireframe,
+}
+
+impl PolygonMode {
+    pub fn next(self) -> Self {
+        match self {
+            PolygonMode::Fill => PolygonMode::Line,
+            PolygonMode::Line => PolygonMode::Point,
+            PolygonMode::Point => PolygonMode::FillWireframe,
+            PolygonMode::FillWireframe => PolygonMode::Fill,
+        }
+    }
+
+    pub fn fills(self) -> bool {
+        matches!(self, PolygonMode::Fill | PolygonMode::FillWireframe)
+    }
+}
+
+// How a fragment is combined with the color already in the framebuffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    // Opaque: the fragment overwrites the pixel, alpha is ignored
+    Replace,
+    Normal,
+    Multiply,
+    Add,
+    Subtract,
+    Screen,
+}
+
+impl BlendMode {
+    // Blends `src` over `dst` with the matching `Color::blend_*` mode, weighted by the source alpha
+    pub fn apply(self, dst: Color, src: Color) -> Color {
+        let blended = match self {
+            BlendMode::Replace => return src,
+            BlendMode::Normal => dst.blend_normal(&src),
+            BlendMode::Multiply => dst.blend_multiply(&src),
+            BlendMode::Add => dst.blend_add(&src),
+            BlendMode::Subtract => dst.blend_subtract(&src),
+            BlendMode::Screen => dst.blend_screen(&src),
+        };
+
+        dst.lerp(&blended, src.alpha())
+    }
+}
+
+// Comparison used by the stencil test
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    Always,
+}
+
+impl CompareFunc {
+    pub fn compare(self, value: u8, stored: u8) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => value < stored,
+            CompareFunc::LessEqual => value <= stored,
+            CompareFunc::Greater => value > stored,
+            CompareFunc::GreaterEqual => value >= stored,
+            CompareFunc::Equal => value == stored,
+            CompareFunc::NotEqual => value != stored,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+// What happens to a stored stencil value after the stencil and depth tests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    Increment,
+    IncrementWrap,
+    Decrement,
+    DecrementWrap,
+    Invert,
+}
+
+impl StencilOp {
+    pub fn apply(self, stored: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::Increment => stored.saturating_add(1),
+            StencilOp::IncrementWrap => stored.wrapping_add(1),
+            StencilOp::Decrement => stored.saturating_sub(1),
+            StencilOp::DecrementWrap => stored.wrapping_sub(1),
+            StencilOp::Invert => !stored,
+        }
+    }
+}
+
+// Stencil test configuration. A sample passes when
+// `(reference & read_mask) func (stored & read_mask)` holds.
+#[derive(Clone, Copy, Debug)]
+pub struct StencilState {
+    pub enabled: bool,
+    pub func: CompareFunc,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    // Stencil test failed
+    pub fail: StencilOp,
+    // Stencil test passed but the depth test failed
+    pub depth_fail: StencilOp,
+    // Both tests passed
+    pub pass: StencilOp,
+}
+
+impl StencilState {
+    // Writes `reference` wherever the draw is visible
+    pub fn write(reference: u8) -> Self {
+        StencilState {
+            enabled: true,
+            pass: StencilOp::Replace,
+            reference,
+            ..Default::default()
+        }
+    }
+
+    // Only lets the draw through where `func` holds against `reference`
+    pub fn test(func: CompareFunc, reference: u8) -> Self {
+        StencilState {
+            enabled: true,
+            func,
+            reference,
+            ..Default::default()
+        }
+    }
+
+    pub fn passes(&self, stored: u8) -> bool {
+        !self.enabled || self.func.compare(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    // New stored value after applying `op`, honoring the write mask
+    pub fn update(&self, stored: u8, op: StencilOp) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (value & self.write_mask)
+    }
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        StencilState {
+            enabled: false,
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+// Fixed-function state used by `render` for a draw call
+#[derive(Clone, Copy, Debug)]
+pub struct RenderState {
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub interpolation: Interpolation,
+    pub polygon_mode: PolygonMode,
+    pub blend_mode: BlendMode,
+    pub color_write: bool,
+    pub depth_write: bool,
+    pub stencil: StencilState,
+    // Test depth before running the fragment shader. Shaders whose result has to
+    // reach the depth test unconditionally (late-Z) turn this off.
+    pub early_depth_test: bool,
+    // Fragments with a lower alpha are discarded, so translucent surfaces can
+    // still fill depth-only passes such as shadow maps where they are opaque enough
+    pub alpha_test: Option<f32>,
+}
+
+impl RenderState {
+    // State for open or flat geometry that has to be visible from both sides
+    pub fn double_sided() -> Self {
+        RenderState {
+            cull_mode: CullMode::None,
+            ..Default::default()
+        }
+    }
+
+    // Same state for translucent surfaces: blended over what is behind them and
+    // depth tested, but without hiding anything drawn after them
+    pub fn with_blend(self, blend_mode: BlendMode) -> Self {
+        RenderState {
+            blend_mode,
+            depth_write: false,
+            ..self
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.blend_mode != BlendMode::Replace
+    }
+
+    // Decides from the screen-space signed area of a triangle whether it gets discarded.
+    // The viewport flips y, so a counter-clockwise triangle in NDC has a positive area here.
+    pub fn is_culled(&self, signed_area: f32) -> bool {
+        // Degenerate triangles never cover a pixel
+        if signed_area == 0.0 {
+            return true;
+        }
+
+        let is_front = match self.front_face {
+            FrontFace::CounterClockwise => signed_area > 0.0,
+            FrontFace::Clockwise => signed_area < 0.0,
+        };
+
+        match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Back => !is_front,
+            CullMode::Front => is_front,
+        }
+    }
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        RenderState {
+            cull_mode: CullMode::Back,
+            front_face: FrontFace::CounterClockwise,
+            interpolation: Interpolation::Perspective,
+            polygon_mode: PolygonMode::Fill,
+            blend_mode: BlendMode::Replace,
+            color_write: true,
+            depth_write: true,
+            stencil: StencilState::default(),
+            early_depth_test: true,
+            alpha_test: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_blend_weights_black_by_its_alpha() {
+        let white = Color::new(255, 255, 255);
+        let half_black = Color::black().with_alpha(0.5);
+
+        let blended = BlendMode::Normal.apply(white, half_black).to_hex();
+        assert!(blended == 0x7F7F7F || blended == 0x808080, "{:06x}", blended);
+
+        // Fully transparent black leaves the destination alone
+        assert_eq!(BlendMode::Normal.apply(white, Color::black().with_alpha(0.0)).to_hex(), 0xFFFFFF);
+    }
+
+    #[test]
+    fn stencil_compares_through_the_read_mask() {
+        let state = StencilState {
+            read_mask: 0x0F,
+            ..StencilState::test(CompareFunc::Equal, 0x35)
+        };
+
+        // Only the low nibble takes part in the comparison
+        assert!(state.passes(0x05));
+        assert!(state.passes(0xA5));
+        assert!(!state.passes(0x36));
+
+        let less = StencilState { read_mask: 0xF0, ..StencilState::test(CompareFunc::Less, 0x1F) };
+        assert!(less.passes(0x20));
+        assert!(!less.passes(0x1F));
+
+        // A disabled stencil lets everything through
+        assert!(StencilState { enabled: false, ..StencilState::test(CompareFunc::Never, 0) }.passes(0));
+    }
+
+    #[test]
+    fn stencil_ops_wrap_or_saturate() {
+        assert_eq!(StencilOp::Increment.apply(255, 0), 255);
+        assert_eq!(StencilOp::IncrementWrap.apply(255, 0), 0);
+        assert_eq!(StencilOp::Decrement.apply(0, 0), 0);
+        assert_eq!(StencilOp::DecrementWrap.apply(0, 0), 255);
+        assert_eq!(StencilOp::Invert.apply(0b1010_0000, 0), 0b0101_1111);
+        assert_eq!(StencilOp::Replace.apply(3, 7), 7);
+        assert_eq!(StencilOp::Zero.apply(3, 7), 0);
+        assert_eq!(StencilOp::Keep.apply(3, 7), 3);
+    }
+
+    #[test]
+    fn stencil_writes_only_through_the_write_mask() {
+        let state = StencilState {
+            write_mask: 0x0F,
+            ..StencilState::write(0xAB)
+        };
+        assert_eq!(state.update(0x50, StencilOp::Replace), 0x5B);
+        assert_eq!(state.update(0x5F, StencilOp::IncrementWrap), 0x50);
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::texture::Texture;
+
+// An offscreen framebuffer with its own camera. Its size is independent of the
+// window, and after rendering into it the result can be bound as a texture.
+pub struct RenderTarget {
+    pub framebuffer: Framebuffer,
+    pub camera: Camera,
+    texture: Arc<Texture>,
+}
+
+impl RenderTarget {
+    pub fn new(width: usize, height: usize, camera: Camera) -> Self {
+        let framebuffer = Framebuffer::new(width, height);
+        let texture = Arc::new(Texture::from_framebuffer(&framebuffer));
+
+        RenderTarget {
+            framebuffer,
+            camera,
+            texture,
+        }
+    }
+
+    // Resolves what was rendered and publishes it as the target's texture
+    pub fn update_texture(&mut self) {
+        self.framebuffer.resolve();
+        self.texture = Arc::new(Texture::from_framebuffer(&self.framebuffer));
+    }
+
+    // Last published image; shaders keep sampling it while the next frame is rendered
+    pub fn texture(&self) -> Arc<Texture> {
+        Arc::clone(&self.texture)
+    }
+}
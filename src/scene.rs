@@ -0,0 +1,661 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::SplitWhitespace;
+use nalgebra_glm::{Mat4, Vec3};
+use crate::background::{Background, Starfield};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::light::Light;
+use crate::model::{IndexedMesh, Obj};
+use crate::render_state::{BlendMode, CullMode, FrontFace, RenderState};
+use crate::render_target::RenderTarget;
+use crate::scene_graph::{SceneGraph, Transform};
+use crate::shader::{FragmentHandle, Param, ShaderRegistry, VertexHandle};
+use crate::shaders;
+
+// Something drawn at a node of the scene graph
+pub struct SceneObject {
+    pub node: usize,
+    // World matrix of the node, refreshed by `Scene::update`
+    pub model_matrix: Mat4,
+    pub mesh: Rc<IndexedMesh>,
+    pub vertex_shader: VertexHandle,
+    pub fragment_shader: FragmentHandle,
+    pub render_state: RenderState,
+    // Render target whose image is bound as the object's texture
+    pub render_target: Option<usize>,
+    // Off for objects that hold a light inside, like the sun
+    pub casts_shadows: bool,
+}
+
+impl SceneObject {
+    pub fn position(&self) -> Vec3 {
+        self.model_matrix.column(3).xyz()
+    }
+
+    // Radius of a sphere around `position` that holds the whole mesh
+    pub fn bounding_radius(&self) -> f32 {
+        let scale = (0..3).map(|i| self.model_matrix.column(i).xyz().magnitude()).fold(0.0, f32::max);
+        scale * self.mesh.bounding_radius()
+    }
+}
+
+// Everything a scene file declares. Statements go one per line, `#` starts a
+// comment, and names must be declared before they are used:
+//
+//     mesh <name> <path.obj>
+//     mesh <name> quad <width> <height>
+//     shader <name> <builtin shader> [<parameter> <numbers...> | <parameter> <path>]...
+//     node <name> [parent <node>] [position x y z] [rotation x y z] [scale s] [spin x y z]
+//     object <name> <mesh> <shader> [parent <node>] [position x y z] [rotation x y z]
+//         [scale s] [spin x y z] [vertex <shader>] [double_sided] [cull none|back|front]
+//         [front_face ccw|cw] [blend <mode>] [target <name>] [no_shadows] [late_z]
+//     body <object> [<object>...]
+//     target <name> <width> <height> eye x y z center x y z [up x y z] draws <object>...
+//     light directional direction x y z [color r g b] [intensity i] [no_shadows]
+//     light point position x y z range r [color r g b] [intensity i] [no_shadows]
+//     light spot position x y z direction x y z range r angles <inner> <outer>
+//         [color r g b] [intensity i] [no_shadows]
+//     ambient r g b
+//     camera eye x y z center x y z [up x y z]
+//     background starfield [seed]
+//     background <image or cube map directory>
+//
+// Every object is also a node, named like the object, so other nodes can hang
+// from it. Transforms are relative to the parent node, and `spin` turns a node
+// around its own axes by some degrees per frame, carrying its children along.
+// Angles are in degrees and colors go from 0 to 255.
+pub struct Scene {
+    pub shaders: ShaderRegistry,
+    pub graph: SceneGraph,
+    pub objects: Vec<SceneObject>,
+    // Groups of objects the camera can focus on, e.g. a planet and its clouds
+    pub bodies: Vec<Vec<usize>>,
+    // Offscreen cameras and the objects each one draws
+    pub render_targets: Vec<(RenderTarget, Vec<usize>)>,
+    pub lights: Vec<Light>,
+    pub ambient: Color,
+    pub camera: Camera,
+    pub background: Option<Background>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    // A statement that could not be understood, with its 1-based line number
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "{}", error),
+            SceneError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(error: std::io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl Scene {
+    // Paths inside the file are relative to the working directory, like the rest of the assets
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        Scene::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, SceneError> {
+        let mut parser = Parser::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let statement = line.split('#').next().unwrap_or_default();
+            let mut tokens = Tokens(statement.split_whitespace());
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            parser
+                .statement(keyword, &mut tokens)
+                .and_then(|()| tokens.end())
+                .map_err(|message| SceneError::Parse { line: line_number, message })?;
+        }
+
+        let mut scene = parser.finish();
+        scene.update(0);
+        Ok(scene)
+    }
+
+    // Moves the nodes to where they are at the given frame
+    pub fn update(&mut self, time: u32) {
+        self.graph.update(time);
+        for object in self.objects.iter_mut() {
+            object.model_matrix = self.graph.world_matrix(object.node);
+        }
+    }
+
+    // Center of a body, at its first object, and the radius of a sphere there that holds it
+    pub fn body_bounds(&self, body: usize) -> (Vec3, f32) {
+        let objects = &self.bodies[body];
+        let center = self.objects[objects[0]].position();
+        let radius = objects
+            .iter()
+            .map(|&i| (self.objects[i].position() - center).magnitude() + self.objects[i].bounding_radius())
+            .fold(0.0, f32::max);
+        (center, radius)
+    }
+}
+
+// Words of one statement
+struct Tokens<'a>(SplitWhitespace<'a>);
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        self.0.next()
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, String> {
+        self.next().ok_or_else(|| format!("expected {}", what))
+    }
+
+    fn float(&mut self, what: &str) -> Result<f32, String> {
+        let word = self.word(what)?;
+        word.parse().map_err(|_| format!("expected {}, found `{}`", what, word))
+    }
+
+    fn vec3(&mut self, what: &str) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.float(what)?, self.float(what)?, self.float(what)?))
+    }
+
+    fn color(&mut self, what: &str) -> Result<Color, String> {
+        let mut channel = || {
+            let value = self.float(what)?;
+            if !(0.0..=255.0).contains(&value) {
+                return Err(format!("{} channels go from 0 to 255, found {}", what, value));
+            }
+            Ok(value as u8)
+        };
+        Ok(Color::new(channel()?, channel()?, channel()?))
+    }
+
+    // Shader parameters: a name followed by its numbers or a file path, up to the next
+    // name. Any word with a `/` or `.` that is not a number is taken as a path.
+    fn params(&mut self) -> Result<Vec<Param>, String> {
+        let mut params: Vec<Param> = Vec::new();
+        for word in self.0.by_ref() {
+            let is_path = word.contains(['/', '.']);
+            match (word.parse(), params.last_mut()) {
+                (Ok(value), Some(param)) => param.values.push(value),
+                (Err(_), Some(param)) if is_path && param.path.is_none() => param.path = Some(word.to_string()),
+                (Err(_), _) if !is_path => params.push(Param { name: word.to_string(), values: Vec::new(), path: None }),
+                _ => return Err(format!("expected a parameter name, found `{}`", word)),
+            }
+        }
+        Ok(params)
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(word) => Err(format!("unexpected `{}`", word)),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Parser {
+    shaders: ShaderRegistry,
+    graph: SceneGraph,
+    meshes: HashMap<String, Rc<IndexedMesh>>,
+    objects: Vec<SceneObject>,
+    object_names: HashMap<String, usize>,
+    bodies: Vec<Vec<usize>>,
+    render_targets: Vec<(RenderTarget, Vec<usize>)>,
+    target_names: HashMap<String, usize>,
+    lights: Vec<Light>,
+    ambient: Color,
+    camera: Camera,
+    background: Option<Background>,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Parser {
+            shaders: shaders::registry(),
+            graph: SceneGraph::default(),
+            meshes: HashMap::new(),
+            objects: Vec::new(),
+            object_names: HashMap::new(),
+            bodies: Vec::new(),
+            render_targets: Vec::new(),
+            target_names: HashMap::new(),
+            lights: Vec::new(),
+            ambient: Color::black(),
+            camera: Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0)),
+            background: None,
+        }
+    }
+
+    fn statement(&mut self, keyword: &str, tokens: &mut Tokens) -> Result<(), String> {
+        match keyword {
+            "mesh" => self.mesh(tokens),
+            "shader" => self.shader(tokens),
+            "node" => {
+                let name = tokens.word("node name")?;
+                let mut placement = Placement::default();
+                while let Some(option) = tokens.next() {
+                    if !self.placement_option(option, tokens, &mut placement)? {
+                        return Err(format!("unknown node option `{}`", option));
+                    }
+                }
+                self.add_node(name, placement).map(|_| ())
+            }
+            "object" => self.object(tokens),
+            "body" => self.body(tokens),
+            "target" => self.target(tokens),
+            "light" => self.light(tokens),
+            "ambient" => {
+                self.ambient = tokens.color("ambient color")?;
+                Ok(())
+            }
+            "camera" => {
+                self.camera = camera(tokens, None)?;
+                Ok(())
+            }
+            "background" => self.background(tokens),
+            _ => Err(format!("unknown statement `{}`", keyword)),
+        }
+    }
+
+    fn mesh(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("mesh name")?;
+        let source = tokens.word("OBJ path or `quad`")?;
+
+        let mesh = if source == "quad" {
+            IndexedMesh::quad(tokens.float("quad width")?, tokens.float("quad height")?)
+        } else {
+            Obj::load(source)
+                .map_err(|error| format!("could not load `{}`: {}", source, error))?
+                .get_indexed_mesh()
+        };
+
+        self.meshes.insert(name.to_string(), Rc::new(mesh));
+        Ok(())
+    }
+
+    fn shader(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("shader name")?;
+        let kind = tokens.word("builtin shader")?;
+        let shader = self.shaders.create_fragment(kind, &tokens.params()?)?;
+
+        self.shaders.register_fragment(name, shader)?;
+        Ok(())
+    }
+
+    fn object(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("object name")?;
+        let mesh = lookup(&self.meshes, "mesh", tokens.word("mesh")?)?;
+        let fragment = tokens.word("shader")?;
+        let fragment_shader = self.shaders.resolve_fragment(fragment)?;
+
+        let mut placement = Placement::default();
+        let mut object = SceneObject {
+            node: 0,
+            model_matrix: Mat4::identity(),
+            mesh: Rc::clone(mesh),
+            vertex_shader: self.vertex_shader("standard")?,
+            fragment_shader,
+            render_state: RenderState::default(),
+            render_target: None,
+            casts_shadows: true,
+        };
+
+        while let Some(option) = tokens.next() {
+            if self.placement_option(option, tokens, &mut placement)? {
+                continue;
+            }
+            match option {
+                "vertex" => object.vertex_shader = self.vertex_shader(tokens.word("vertex shader")?)?,
+                "double_sided" => object.render_state.cull_mode = CullMode::None,
+                "cull" => object.render_state.cull_mode = cull_mode(tokens.word("cull mode")?)?,
+                // Winding of the front faces as seen on screen, for meshes wound clockwise
+                "front_face" => object.render_state.front_face = front_face(tokens.word("front face")?)?,
+                // Blended objects are drawn after the opaque ones and leave depth alone
+                "blend" => object.render_state = object.render_state.with_blend(blend_mode(tokens.word("blend mode")?)?),
+                "target" => object.render_target = Some(*lookup(&self.target_names, "target", tokens.word("target")?)?),
+                "no_shadows" => object.casts_shadows = false,
+                // Shade every fragment before the depth test instead of only the visible ones
+                "late_z" => object.render_state.early_depth_test = false,
+                _ => return Err(format!("unknown object option `{}`", option)),
+            }
+        }
+
+        object.node = self.add_node(name, placement)?;
+        self.object_names.insert(name.to_string(), self.objects.len());
+        self.objects.push(object);
+        Ok(())
+    }
+
+    // Reads an option that places a node; false if the option is something else
+    fn placement_option(&self, option: &str, tokens: &mut Tokens, placement: &mut Placement) -> Result<bool, String> {
+        let transform = &mut placement.transform;
+        match option {
+            "parent" => {
+                let parent = tokens.word("parent node")?;
+                placement.parent = Some(self.graph.find(parent).ok_or_else(|| format!("unknown node `{}`", parent))?);
+            }
+            "position" => transform.translation = tokens.vec3("position")?,
+            "rotation" => transform.rotation = tokens.vec3("rotation in degrees")?.map(f32::to_radians),
+            "scale" => transform.scale = tokens.float("scale")?,
+            "spin" => placement.spin = tokens.vec3("spin in degrees per frame")?.map(f32::to_radians),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn add_node(&mut self, name: &str, placement: Placement) -> Result<usize, String> {
+        if self.graph.find(name).is_some() {
+            return Err(format!("there is already a node called `{}`", name));
+        }
+        Ok(self.graph.add(name, placement.transform, placement.spin, placement.parent))
+    }
+
+    fn vertex_shader(&self, name: &str) -> Result<VertexHandle, String> {
+        self.shaders.vertex_handle(name).ok_or_else(|| format!("unknown vertex shader `{}`", name))
+    }
+
+    fn body(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let objects = self.object_list(tokens)?;
+        if objects.is_empty() {
+            return Err("expected the objects of the body".to_string());
+        }
+
+        self.bodies.push(objects);
+        Ok(())
+    }
+
+    fn object_list(&self, tokens: &mut Tokens) -> Result<Vec<usize>, String> {
+        let mut objects = Vec::new();
+        while let Some(name) = tokens.next() {
+            objects.push(*lookup(&self.object_names, "object", name)?);
+        }
+        Ok(objects)
+    }
+
+    fn target(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("target name")?;
+        let width = tokens.float("target width")?;
+        let height = tokens.float("target height")?;
+        if width < 1.0 || height < 1.0 {
+            return Err("a target needs at least one pixel".to_string());
+        }
+
+        let camera = camera(tokens, Some("draws"))?;
+        let draws = self.object_list(tokens)?;
+        if draws.is_empty() {
+            return Err("a target needs `draws` followed by its objects".to_string());
+        }
+
+        self.target_names.insert(name.to_string(), self.render_targets.len());
+        self.render_targets.push((RenderTarget::new(width as usize, height as usize, camera), draws));
+        Ok(())
+    }
+
+    fn light(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let kind = tokens.word("light kind")?;
+        let mut position = None;
+        let mut direction = None;
+        let mut range = None;
+        let mut angles = None;
+        let mut color = Color::new(255, 255, 255);
+        let mut intensity = 1.0;
+        let mut casts_shadows = true;
+
+        while let Some(option) = tokens.next() {
+            match option {
+                "position" => position = Some(tokens.vec3("position")?),
+                "direction" => direction = Some(tokens.vec3("direction")?),
+                "range" => {
+                    // The falloff halves at `range`, which only works out past 0
+                    let value = tokens.float("range")?;
+                    if value.is_nan() || value <= 0.0 {
+                        return Err(format!("a light range must be greater than 0, found {}", value));
+                    }
+                    range = Some(value);
+                }
+                "angles" => angles = Some((tokens.float("inner angle")?.to_radians(), tokens.float("outer angle")?.to_radians())),
+                "color" => color = tokens.color("light color")?,
+                "intensity" => intensity = tokens.float("intensity")?,
+                "no_shadows" => casts_shadows = false,
+                _ => return Err(format!("unknown light option `{}`", option)),
+            }
+        }
+
+        let missing = |what: &str| format!("a {} light needs a {}", kind, what);
+        let light = match kind {
+            "directional" => Light::directional(direction.ok_or_else(|| missing("direction"))?, color, intensity),
+            "point" => Light::point(
+                position.ok_or_else(|| missing("position"))?,
+                range.ok_or_else(|| missing("range"))?,
+                color,
+                intensity,
+            ),
+            "spot" => {
+                let (inner, outer) = angles.ok_or_else(|| missing("pair of angles"))?;
+                Light::spot(
+                    position.ok_or_else(|| missing("position"))?,
+                    direction.ok_or_else(|| missing("direction"))?,
+                    range.ok_or_else(|| missing("range"))?,
+                    inner,
+                    outer,
+                    color,
+                    intensity,
+                )
+            }
+            _ => return Err(format!("unknown light kind `{}`", kind)),
+        };
+
+        self.lights.push(Light { casts_shadows, ..light });
+        Ok(())
+    }
+
+    fn background(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let source = tokens.word("`starfield` or a background path")?;
+
+        self.background = Some(if source == "starfield" {
+            let seed = match tokens.next() {
+                Some(word) => word.parse().map_err(|_| format!("expected a starfield seed, found `{}`", word))?,
+                None => 0,
+            };
+            Background::Starfield(Starfield::new(seed))
+        } else {
+            Background::load(source).map_err(|error| format!("could not load `{}`: {}", source, error))?
+        });
+        Ok(())
+    }
+
+    fn finish(self) -> Scene {
+        // Without bodies every object stands on its own
+        let bodies = if self.bodies.is_empty() {
+            (0..self.objects.len()).map(|i| vec![i]).collect()
+        } else {
+            self.bodies
+        };
+
+        Scene {
+            shaders: self.shaders,
+            graph: self.graph,
+            objects: self.objects,
+            bodies,
+            render_targets: self.render_targets,
+            lights: self.lights,
+            ambient: self.ambient,
+            camera: self.camera,
+            background: self.background,
+        }
+    }
+}
+
+// Where a node goes in the graph
+#[derive(Default)]
+struct Placement {
+    transform: Transform,
+    spin: Vec3,
+    parent: Option<usize>,
+}
+
+fn lookup<'a, T>(names: &'a HashMap<String, T>, what: &str, name: &str) -> Result<&'a T, String> {
+    names.get(name).ok_or_else(|| format!("unknown {} `{}`", what, name))
+}
+
+// Camera options up to the end of the statement, or up to the word `until`
+fn camera(tokens: &mut Tokens, until: Option<&str>) -> Result<Camera, String> {
+    let mut eye = None;
+    let mut center = None;
+    let mut up = Vec3::new(0.0, 1.0, 0.0);
+
+    while let Some(option) = tokens.next() {
+        match option {
+            "eye" => eye = Some(tokens.vec3("eye")?),
+            "center" => center = Some(tokens.vec3("center")?),
+            "up" => up = tokens.vec3("up")?,
+            _ if Some(option) == until => break,
+            _ => return Err(format!("unknown camera option `{}`", option)),
+        }
+    }
+
+    Ok(Camera::new(
+        eye.ok_or("a camera needs an eye position")?,
+        center.ok_or("a camera needs a center")?,
+        up,
+    ))
+}
+
+fn cull_mode(name: &str) -> Result<CullMode, String> {
+    match name {
+        "none" => Ok(CullMode::None),
+        "back" => Ok(CullMode::Back),
+        "front" => Ok(CullMode::Front),
+        _ => Err(format!("unknown cull mode `{}`", name)),
+    }
+}
+
+fn front_face(name: &str) -> Result<FrontFace, String> {
+    match name {
+        "ccw" => Ok(FrontFace::CounterClockwise),
+        "cw" => Ok(FrontFace::Clockwise),
+        _ => Err(format!("unknown front face `{}`, expected `ccw` or `cw`", name)),
+    }
+}
+
+fn blend_mode(name: &str) -> Result<BlendMode, String> {
+    match name {
+        "normal" => Ok(BlendMode::Normal),
+        "multiply" => Ok(BlendMode::Multiply),
+        "add" => Ok(BlendMode::Add),
+        "subtract" => Ok(BlendMode::Subtract),
+        "screen" => Ok(BlendMode::Screen),
+        _ => Err(format!("unknown blend mode `{}`", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(source: &str) -> (usize, String) {
+        match Scene::parse(source) {
+            Err(SceneError::Parse { line, message }) => (line, message),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("the scene should not parse"),
+        }
+    }
+
+    #[test]
+    fn default_scene_loads() {
+        let scene = Scene::load(crate::DEFAULT_SCENE).unwrap();
+        assert_eq!(scene.objects.len(), 11);
+        assert_eq!(scene.bodies.len(), 8);
+        assert_eq!(scene.lights.len(), 3);
+        assert_eq!(scene.render_targets.len(), 1);
+        assert_eq!(scene.render_targets[0].1, vec![3, 8]);
+        // The sun holds the point light, so it must not hide it
+        assert!(!scene.objects[2].casts_shadows);
+
+        // The earth body is centered on the earth and holds its clouds
+        let (center, radius) = scene.body_bounds(3);
+        assert!((center - scene.objects[3].position()).magnitude() < 1e-5);
+        assert!(radius >= scene.objects[8].bounding_radius());
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let source = "mesh screen quad 1 1\n\n# comment\nobject a screen nope\n";
+        let (line, message) = error_line(source);
+        assert_eq!(line, 4);
+        assert!(message.contains("nope"), "{}", message);
+
+        assert_eq!(error_line("ambient 20 20\n").0, 1);
+        assert_eq!(error_line("mesh screen quad 1 1\nobject a screen sun scale 1 2\n").0, 2);
+        assert_eq!(error_line("camera eye 0 0 5 center 0 0 0 draws\n").0, 1);
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let source = "mesh screen quad 1 1\nnode pivot position 0 1 0 spin 0 90 0\nobject a screen sun parent pivot position 2 0 0 scale 0.5\n";
+        let mut scene = Scene::parse(source).unwrap();
+        assert!((scene.objects[0].position() - Vec3::new(2.0, 1.0, 0.0)).magnitude() < 1e-5);
+
+        // A quarter turn of the parent carries the child from +x to -z
+        scene.update(1);
+        assert!((scene.objects[0].position() - Vec3::new(0.0, 1.0, -2.0)).magnitude() < 1e-5);
+
+        assert!(error_line("mesh screen quad 1 1\nobject a screen sun parent nope\n").1.contains("nope"));
+        assert_eq!(error_line("node a\nnode a\n").0, 2);
+    }
+
+    #[test]
+    fn shaders_take_parameters() {
+        let source = "mesh screen quad 1 1\nshader red_moon moon color3 255 0 0\nobject a screen red_moon\n";
+        let scene = Scene::parse(source).unwrap();
+        assert_eq!(scene.shaders.fragment_handle("red_moon"), Some(scene.objects[0].fragment_shader));
+
+        assert!(error_line("shader x moon color3 300 0 0\n").1.contains("color3"));
+        assert!(error_line("shader x moon speed 1\n").1.contains("speed"));
+        assert!(error_line("shader x nope\n").1.contains("nope"));
+
+        // Paths load when the shader is declared, so a missing file points at its line
+        assert!(Scene::parse("shader bumpy blinn_phong normal_map assets/textures/earth.jpg\n").is_ok());
+        let (line, message) = error_line("ambient 0 0 0\nshader bumpy blinn_phong normal_map assets/nope.png\n");
+        assert_eq!(line, 2);
+        assert!(message.contains("assets/nope.png"), "{}", message);
+        assert!(error_line("shader bumpy blinn_phong normal_map 1\n").1.contains("path"));
+        assert!(error_line("shader bumpy blinn_phong normal_map a.png b.png\n").1.contains("b.png"));
+    }
+
+    #[test]
+    fn light_ranges_must_be_positive() {
+        let source = "light point position 0 0 0 range 12\n";
+        assert_eq!(Scene::parse(source).unwrap().lights.len(), 1);
+
+        assert_eq!(error_line("ambient 20 20 30\nlight point position 0 0 0 range 0\n").0, 2);
+        assert!(error_line("light spot position 0 0 0 direction 0 -1 0 range -3 angles 10 20\n").1.contains("range"));
+    }
+
+    #[test]
+    fn objects_choose_their_culling() {
+        let source = "mesh screen quad 1 1\nobject a screen sun cull front front_face cw\nobject b screen sun\n";
+        let scene = Scene::parse(source).unwrap();
+        assert_eq!(scene.objects[0].render_state.cull_mode, CullMode::Front);
+        assert_eq!(scene.objects[0].render_state.front_face, FrontFace::Clockwise);
+        assert_eq!(scene.objects[1].render_state.cull_mode, CullMode::Back);
+        assert_eq!(scene.objects[1].render_state.front_face, FrontFace::CounterClockwise);
+
+        assert!(error_line("mesh screen quad 1 1\nobject a screen sun cull sides\n").1.contains("sides"));
+        assert!(error_line("mesh screen quad 1 1\nobject a screen sun front_face up\n").1.contains("up"));
+    }
+}
@@ -0,0 +1,94 @@
+use nalgebra_glm::{Mat4, Vec3};
+use crate::create_model_matrix;
+
+// Position, orientation (Euler angles in radians) and uniform size of a node
+// relative to its parent
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vec3::zeros(),
+            rotation: Vec3::zeros(),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        create_model_matrix(self.translation, self.scale, self.rotation)
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    // Radians per frame turned around the node's own axes, after its rotation.
+    // Children turn along, so a spinning node with an offset child makes an orbit.
+    pub spin: Vec3,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    world_matrix: Mat4,
+}
+
+// A tree of transforms. Parents are always added before their children, so
+// indices into the graph stay valid and a node can never be its own ancestor.
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+}
+
+impl SceneGraph {
+    pub fn add(&mut self, name: &str, transform: Transform, spin: Vec3, parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(index);
+        }
+
+        self.nodes.push(Node {
+            name: name.to_string(),
+            transform,
+            spin,
+            parent,
+            children: Vec::new(),
+            world_matrix: Mat4::identity(),
+        });
+        index
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    // Recomputes every world matrix for the given frame, from the roots down
+    pub fn update(&mut self, time: u32) {
+        for root in 0..self.nodes.len() {
+            if self.nodes[root].parent.is_none() {
+                self.propagate(root, &Mat4::identity(), time);
+            }
+        }
+    }
+
+    fn propagate(&mut self, index: usize, parent_matrix: &Mat4, time: u32) {
+        let node = &self.nodes[index];
+        let spin = create_model_matrix(Vec3::zeros(), 1.0, node.spin * time as f32);
+        let world_matrix = parent_matrix * node.transform.matrix() * spin;
+        self.nodes[index].world_matrix = world_matrix;
+
+        for i in 0..self.nodes[index].children.len() {
+            let child = self.nodes[index].children[i];
+            self.propagate(child, &world_matrix, time);
+        }
+    }
+
+    // Model matrix of the node in world space, as of the last update
+    pub fn world_matrix(&self, index: usize) -> Mat4 {
+        self.nodes[index].world_matrix
+    }
+}
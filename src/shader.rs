@@ -0,0 +1,216 @@
+use crate::color::Color;
+use crate::fragment::Fragment;
+use crate::light::shade;
+use crate::surface::{Surface, NO_MATERIAL};
+use crate::vertex::Vertex;
+use crate::Uniforms;
+
+// Runs once per unique vertex of a draw. Shaders are shared by the tiles that
+// are rasterized in parallel, so they must be `Send + Sync`.
+pub trait VertexShader: Send + Sync {
+    fn shade(&self, vertex: &Vertex, uniforms: &Uniforms) -> Vertex;
+}
+
+// Describes the surface under a fragment without lighting it. The parameters of
+// the material live in the type that implements it.
+pub trait FragmentShader: Send + Sync {
+    fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface;
+}
+
+// Handles into a `ShaderRegistry`; only the registry hands them out, so they always resolve
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FragmentHandle(usize);
+
+// Builds a fragment shader from the parameters written in a scene file
+pub type FragmentFactory = fn(&[Param]) -> Result<Box<dyn FragmentShader>, String>;
+
+// Shaders by name. Registering a name again swaps the shader behind it, and every
+// handle to that name sees the new one. Factories are kept apart, by the name of
+// the kind of shader they build.
+#[derive(Default)]
+pub struct ShaderRegistry {
+    vertex: Vec<(String, Box<dyn VertexShader>)>,
+    fragment: Vec<(String, Box<dyn FragmentShader>)>,
+    factories: Vec<(String, FragmentFactory)>,
+}
+
+impl ShaderRegistry {
+    pub fn register_vertex(&mut self, name: &str, shader: Box<dyn VertexShader>) -> VertexHandle {
+        VertexHandle(insert(&mut self.vertex, name, shader))
+    }
+
+    // Fails once every material ID is taken, as the handle doubles as the ID and
+    // `NO_MATERIAL` is kept for pixels no object covered
+    pub fn register_fragment(&mut self, name: &str, shader: Box<dyn FragmentShader>) -> Result<FragmentHandle, String> {
+        if position(&self.fragment, name).is_none() && self.fragment.len() >= NO_MATERIAL as usize {
+            return Err(format!("too many fragment shaders, at most {} can be registered", NO_MATERIAL));
+        }
+        Ok(FragmentHandle(insert(&mut self.fragment, name, shader)))
+    }
+
+    pub fn register_factory(&mut self, kind: &str, factory: FragmentFactory) {
+        match self.factories.iter_mut().find(|(name, _)| name == kind) {
+            Some((_, registered)) => *registered = factory,
+            None => self.factories.push((kind.to_string(), factory)),
+        }
+    }
+
+    // A new fragment shader of a registered kind, not registered itself
+    pub fn create_fragment(&self, kind: &str, params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+        let (_, factory) = self.factories
+            .iter()
+            .find(|(name, _)| name == kind)
+            .ok_or_else(|| format!("unknown shader `{}`", kind))?;
+        factory(params)
+    }
+
+    pub fn vertex_handle(&self, name: &str) -> Option<VertexHandle> {
+        position(&self.vertex, name).map(VertexHandle)
+    }
+
+    pub fn fragment_handle(&self, name: &str) -> Option<FragmentHandle> {
+        position(&self.fragment, name).map(FragmentHandle)
+    }
+
+    // The shader registered under `name` or, if there is none yet, a new one built
+    // with default parameters by the factory of that name
+    pub fn resolve_fragment(&mut self, name: &str) -> Result<FragmentHandle, String> {
+        if let Some(handle) = self.fragment_handle(name) {
+            return Ok(handle);
+        }
+        let shader = self.create_fragment(name, &[])?;
+        self.register_fragment(name, shader)
+    }
+
+    // The pair of shaders a draw runs with
+    pub fn program(&self, vertex: VertexHandle, fragment: FragmentHandle) -> Program<'_> {
+        Program {
+            vertex: self.vertex[vertex.0].1.as_ref(),
+            fragment: self.fragment[fragment.0].1.as_ref(),
+            // The G-buffer tells materials apart by the fragment shader. Handles stay
+            // below `NO_MATERIAL`, see `register_fragment`.
+            material: fragment.0 as u8,
+        }
+    }
+}
+
+fn insert<T: ?Sized>(shaders: &mut Vec<(String, Box<T>)>, name: &str, shader: Box<T>) -> usize {
+    match position(shaders, name) {
+        Some(i) => {
+            shaders[i].1 = shader;
+            i
+        }
+        None => {
+            shaders.push((name.to_string(), shader));
+            shaders.len() - 1
+        }
+    }
+}
+
+fn position<T: ?Sized>(shaders: &[(String, Box<T>)], name: &str) -> Option<usize> {
+    shaders.iter().position(|(shader_name, _)| shader_name == name)
+}
+
+// Shaders resolved from the registry for one draw
+#[derive(Clone, Copy)]
+pub struct Program<'a> {
+    pub vertex: &'a dyn VertexShader,
+    pub fragment: &'a dyn FragmentShader,
+    // Material ID written to the G-buffer
+    pub material: u8,
+}
+
+impl Program<'_> {
+    pub fn shade_vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
+        self.vertex.shade(vertex, uniforms)
+    }
+
+    // Deferred path: the surface goes to the G-buffer tagged with the material ID
+    pub fn surface(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+        Surface { material: self.material, ..self.fragment.shade(fragment, uniforms) }
+    }
+
+    // Forward path: the surface is lit by the scene lights as soon as it is shaded
+    pub fn color(&self, fragment: &Fragment, uniforms: &Uniforms) -> Color {
+        shade(&self.surface(fragment, uniforms), uniforms.lights, uniforms.ambient, &uniforms.camera_position)
+    }
+}
+
+// A named shader parameter and its values, as written in a scene file
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: String,
+    pub values: Vec<f32>,
+    // A file the parameter names instead of numbers, e.g. a texture
+    pub path: Option<String>,
+}
+
+impl Param {
+    pub fn float(&self) -> Result<f32, String> {
+        match self.values[..] {
+            [value] => Ok(value),
+            _ => Err(format!("parameter `{}` takes one number", self.name)),
+        }
+    }
+
+    // Three components from 0 to 255
+    pub fn color(&self) -> Result<Color, String> {
+        match self.values[..] {
+            [r, g, b] if [r, g, b].iter().all(|c| (0.0..=255.0).contains(c)) => Ok(Color::new(r as u8, g as u8, b as u8)),
+            _ => Err(format!("parameter `{}` takes a color as three numbers from 0 to 255", self.name)),
+        }
+    }
+
+    // A path relative to the working directory, like the rest of the assets
+    pub fn path(&self) -> Result<&str, String> {
+        match (&self.path, self.values.is_empty()) {
+            (Some(path), true) => Ok(path),
+            _ => Err(format!("parameter `{}` takes a file path", self.name)),
+        }
+    }
+
+    pub fn unknown(&self) -> String {
+        format!("unknown parameter `{}`", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Blank;
+
+    impl FragmentShader for Blank {
+        fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+            Surface::new(fragment, Color::black())
+        }
+    }
+
+    #[test]
+    fn fragment_handles_stop_before_no_material() {
+        let mut shaders = ShaderRegistry::default();
+        for i in 0..NO_MATERIAL {
+            let handle = shaders.register_fragment(&i.to_string(), Box::new(Blank)).unwrap();
+            assert_eq!(handle, FragmentHandle(i as usize));
+        }
+
+        assert!(shaders.register_fragment("one more", Box::new(Blank)).is_err());
+        // Replacing a registered shader takes no new ID
+        assert_eq!(shaders.register_fragment("0", Box::new(Blank)), Ok(FragmentHandle(0)));
+    }
+
+    #[test]
+    fn factories_build_shaders_on_first_use() {
+        let mut shaders = ShaderRegistry::default();
+        shaders.register_factory("blank", |_| Ok(Box::new(Blank)));
+        assert_eq!(shaders.fragment_handle("blank"), None);
+
+        let handle = shaders.resolve_fragment("blank").unwrap();
+        assert_eq!(shaders.fragment_handle("blank"), Some(handle));
+        assert_eq!(shaders.resolve_fragment("blank"), Ok(handle));
+        assert!(shaders.resolve_fragment("missing").is_err());
+    }
+}
@@ -1,185 +1,418 @@
-use nalgebra_glm::{atan2, dot, mat4_to_mat3, Mat3, Vec2, Vec3, Vec4};
+use nalgebra_glm::{mat4_to_mat3, Mat3, Vec2, Vec3, Vec4};
 use crate::vertex::Vertex;
-use crate::{framebuffer, Uniforms};
+use crate::Uniforms;
 use crate::fragment::Fragment;
 use crate::color::Color;
+use crate::surface::Surface;
+use crate::shader::{FragmentShader, Param, ShaderRegistry, VertexShader};
+use crate::texture::{Sampler, Texture, Wrap};
+use crate::noise::{CellularReturnType, Noise, NoiseKind};
+use once_cell::sync::Lazy;
 use std::f32::consts::PI;
 
-pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
-  // Transform position
-  let position = Vec4::new(
-    vertex.position.x,
-    vertex.position.y,
-    vertex.position.z,
-    1.0
-  );
-  let transformed = uniforms.projection_matrix * uniforms.view_matrix * uniforms.model_matrix * position;
-
-  // Perform perspective division
-  let w = transformed.w;
-  let ndc_position = Vec4::new(
-    transformed.x / w,
-    transformed.y / w,
-    transformed.z / w,
-    1.0
-  );
-
-  // apply viewport matrix
-  let screen_position = uniforms.viewport_matrix * ndc_position;
-
-  // Transform normal
-  let model_mat3 = mat4_to_mat3(&uniforms.model_matrix); 
-  let normal_matrix = model_mat3.transpose().try_inverse().unwrap_or(Mat3::identity());
-
-  let transformed_normal = normal_matrix * vertex.normal;
-
-  // Create a new Vertex with transformed attributes
-  Vertex {
-    position: vertex.position,
-    normal: vertex.normal,
-    tex_coords: vertex.tex_coords,
-    color: vertex.color,
-    transformed_position: Vec3::new(screen_position.x, screen_position.y, screen_position.z),
-    transformed_normal,
-  }
-}
-
-pub fn fragment_shader(fragment: &Fragment, uniforms: &Uniforms, id:f32) -> Color {
-  match id {
-    1.0 => planet1(fragment, uniforms),
-    2.0 => moon_shader(fragment, uniforms),
-
-    3.0 => sun_shader(fragment, uniforms),
-
-    4.0 => earth_shader(fragment, uniforms),
-    5.0 => vibrant_blue_planet_shader(fragment, uniforms),
-    
-    6.0 => rocky_planet_shader(fragment, uniforms),
-    7.0 => ring_shader(fragment, uniforms),
-    // Agregar más shaders aquí según sea necesario
-    _ => {
-        // Por defecto, renderiza un shader genérico
-        Color::new(0, 0, 0) // Negro como fallback
-    }
+// Fábricas de los shaders incluidos, registradas con el nombre con el que los piden los
+// objetos. Cada shader se crea la primera vez que un objeto lo usa, así que la escena
+// indica la línea si falla, por ejemplo al cargar una textura.
+pub fn registry() -> ShaderRegistry {
+  let mut shaders = ShaderRegistry::default();
+  shaders.register_vertex("standard", Box::new(StandardVertex));
+
+  shaders.register_factory("gas_giant", GasGiant::from_params);
+  shaders.register_factory("moon", Moon::from_params);
+  shaders.register_factory("sun", Sun::from_params);
+  shaders.register_factory("earth", Earth::from_params);
+  shaders.register_factory("vibrant_gas", VibrantGas::from_params);
+  shaders.register_factory("rocky", RockyPlanet::from_params);
+  shaders.register_factory("ringed_planet", RingedPlanet::from_params);
+  shaders.register_factory("clouds", Clouds::from_params);
+  shaders.register_factory("planet_rings", PlanetRings::from_params);
+  shaders.register_factory("outline", Outline::from_params);
+  shaders.register_factory("monitor", Monitor::from_params);
+  shaders.register_factory("blinn_phong", BlinnPhong::from_params);
+  shaders
+}
+
+// Aplica los parámetros de la escena sobre un shader; los que faltan quedan por defecto
+fn with_params<S, F>(mut shader: S, params: &[Param], set: F) -> Result<Box<dyn FragmentShader>, String>
+where
+  S: FragmentShader + 'static,
+  F: Fn(&mut S, &Param) -> Result<(), String>,
+{
+  for param in params {
+    set(&mut shader, param)?;
+  }
+  Ok(Box::new(shader))
 }
-    
+
+// Lleva los vértices al clip space y sus normales y tangentes al mundo
+pub struct StandardVertex;
+
+impl VertexShader for StandardVertex {
+  fn shade(&self, vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
+    // Transform position into clip space; the perspective division happens after clipping
+    let position = Vec4::new(
+      vertex.position.x,
+      vertex.position.y,
+      vertex.position.z,
+      1.0
+    );
+    let world_position = uniforms.model_matrix * position;
+    let clip_position = uniforms.projection_matrix * uniforms.view_matrix * world_position;
+
+    // Transform normal
+    let model_mat3 = mat4_to_mat3(&uniforms.model_matrix); 
+    let normal_matrix = model_mat3.transpose().try_inverse().unwrap_or(Mat3::identity());
+
+    let transformed_normal = normal_matrix * vertex.normal;
+    // Tangents lie on the surface, so they follow the model matrix like positions do
+    let transformed_tangent = model_mat3 * vertex.tangent;
+    let transformed_bitangent = model_mat3 * vertex.bitangent;
+
+    // Create a new Vertex with transformed attributes
+    Vertex {
+      position: vertex.position,
+      normal: vertex.normal,
+      tangent: vertex.tangent,
+      bitangent: vertex.bitangent,
+      tex_coords: vertex.tex_coords,
+      color: vertex.color,
+      clip_position,
+      transformed_position: vertex.transformed_position,
+      transformed_normal,
+      transformed_tangent,
+      transformed_bitangent,
+      world_position: world_position.xyz(),
+    }
   }
+}
+
+// Turbulencia que deforma las franjas del planeta gaseoso
+static PLANET1_TURBULENCE: Lazy<Noise> = Lazy::new(|| {
+  Noise::new(NoiseKind::Simplex, 11).frequency(3.0).fbm(4).warp(0.15, 2.0)
+});
 
 // planeta 1, planeta gaseoso
-fn planet1(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-   let color1 = Color::new( 85, 117, 114 );   
-   let color2 = Color::new(112, 147, 144  );   
- 
-   let stripe_width = 0.2;  // Width of each stripe
-   let speed = 0.001;        // Speed of stripe movement
- 
-   let moving_y = fragment.vertex_position.y + uniforms.time as f32 * speed;
- 
-   let stripe_factor = ((moving_y / stripe_width) * PI).sin() * 0.5 + 0.5;
- 
-   color1.lerp(&color2, stripe_factor) * fragment.intensity
-}
-
-fn sun_shader(fragment: &Fragment, _uniforms: &Uniforms) -> Color {
-
-
-  // Coordenadas del fragmento normalizadas al rango [-1, 1]
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let center = (0.0, 0.0); // Centro del degradado
-  let radius = ((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt(); // Distancia al centro
-
-  let color1 = Color::new(255, 255, 255); // Amarillo muy claro
-  let color2 = Color::new(255, 230, 28); // Amarillo pastel
-  let color3 = Color::new(255, 178, 51);  // Amarillo intenso
-  let color4 = Color::new(204, 102, 0);   // Naranja oscuro
-
-  let t = radius.clamp(0.0, 1.0); // Radio normalizado entre 0 y 1
-  let blended_color = if t < 0.53 {
-      // Mezcla entre el color1 y color2
-      color1.lerp(&color2, t / 0.33)
-  } else if t < 0.66 {
-      // Mezcla entre el color2 y color3
-      color2.lerp(&color3, (t - 0.33) / 0.33)
-  } else {
-      // Mezcla entre el color3 y color4
-      color3.lerp(&color4, (t - 0.66) / 0.34)
-  };
-
-  blended_color
-}
-
-
-fn earth_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let z = fragment.vertex_position.z;
-
-  let theta = (y / 0.5).asin(); // Latitud
-  let phi = z.atan2(x);         // Longitud
-  let u = (phi / (2.0 * PI)) + 0.5; // Coordenada u [0, 1]
-  let v = (theta / PI) + 0.5;      // Coordenada v [0, 1]
+pub struct GasGiant {
+  pub color1: Color,
+  pub color2: Color,
+  pub stripe_width: f32, // Width of each stripe
+  pub speed: f32,        // Speed of stripe movement
+}
+
+impl Default for GasGiant {
+  fn default() -> Self {
+    GasGiant {
+      color1: Color::new(85, 117, 114),
+      color2: Color::new(112, 147, 144),
+      stripe_width: 0.2,
+      speed: 0.001,
+    }
+  }
+}
+
+impl GasGiant {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(GasGiant::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "color1" => shader.color1 = param.color()?,
+        "color2" => shader.color2 = param.color()?,
+        "stripe_width" => shader.stripe_width = param.float()?,
+        "speed" => shader.speed = param.float()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for GasGiant {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    // Las franjas se ondulan con la turbulencia, que avanza con ellas
+    let drift = Vec3::new(0.0, uniforms.time as f32 * self.speed, 0.0);
+    let turbulence = PLANET1_TURBULENCE.get(&(fragment.vertex_position + drift));
+
+    let moving_y = fragment.vertex_position.y + uniforms.time as f32 * self.speed + turbulence * 0.06;
+
+    let stripe_factor = ((moving_y / self.stripe_width) * PI).sin() * 0.5 + 0.5;
+
+    Surface::new(fragment, self.color1.lerp(&self.color2, stripe_factor))
+  }
+}
 
-  let scale = 7.2;
-  let noise = ((u * scale).sin() * (v * scale).cos()).abs();
-  let continent_threshold = 0.55;
-
-  let land_color = Color::new(34, 139, 34); // Verde
-  let ocean_color = Color::new(0, 105, 148); // Azul
-  let base_color = if noise > continent_threshold { land_color } else { ocean_color };
-
-  // Agregar polos
-  let circle1_center = Vec2::new(0.5, 0.7); // Coordenadas UV del primer círculo
-  let circle2_center = Vec2::new(0.2, 0.3); // Coordenadas UV del segundo círculo
-  let circle_radius = 0.1;                 // Radio de los círculos
-
-  let frag_position = Vec2::new(u, v);
-  let distance_to_circle1 = (frag_position - circle1_center).norm();
-  let distance_to_circle2 = (frag_position - circle2_center).norm();
-
-  // Dibujar círculos: Si la distancia es menor al radio, aplica un color
-  let circle_color = Color::new(255, 255, 255); // Blanco para los círculos
-  let is_in_circle1 = distance_to_circle1 < circle_radius;
-  let is_in_circle2 = distance_to_circle2 < circle_radius;
-
-  // Simular nubes en la atmósfera
-  let time = uniforms.time as f32 * 0.1; // Escala del tiempo para velocidad
-  let cloud_scale = 8.0;          // Escala de las nubes
-  let cloud_pattern = ((u * cloud_scale + time).sin() * (v * cloud_scale + time).cos()).abs();
-  let cloud_intensity = (cloud_pattern - 0.5).clamp(0.0, 1.0) * 0.3; // Intensidad y opacidad de las nubes
-  let cloud_color = Color::new(255, 255, 255) * cloud_intensity;
-
-  // Nubes en movimiento circular hacia afuera
-  let cloud_radius = 0.9; // Límite de la atmósfera con nubes
-  let distance_from_center = frag_position.norm();
-  let is_in_atmosphere = distance_from_center < cloud_radius;
-
-  // Combinar colores
-  let planet_color = if is_in_circle1 || is_in_circle2 {
-      circle_color
-  } else {
-      base_color
-  };
-
-  let final_color = if is_in_atmosphere {
-      planet_color * (1.0 - cloud_intensity) + cloud_color
-  } else {
-      planet_color
-  };
-
-  final_color
-}
-
-pub fn vibrant_blue_planet_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let zoom = 3.0;
+// Gránulos de convección en la superficie del sol
+static SUN_GRANULES: Lazy<Noise> = Lazy::new(|| {
+  Noise::new(NoiseKind::Cellular, 21).frequency(14.0).cellular_return(CellularReturnType::Distance2Sub)
+});
+
+// El sol emite su propia luz, con un degradado del centro hacia el borde
+pub struct Sun {
+  pub color1: Color, // Amarillo muy claro
+  pub color2: Color, // Amarillo pastel
+  pub color3: Color, // Amarillo intenso
+  pub color4: Color, // Naranja oscuro
+  // Cuánto oscurecen los bordes entre gránulos
+  pub granulation: f32,
+}
+
+impl Default for Sun {
+  fn default() -> Self {
+    Sun {
+      color1: Color::new(255, 255, 255),
+      color2: Color::new(255, 230, 28),
+      color3: Color::new(255, 178, 51),
+      color4: Color::new(204, 102, 0),
+      granulation: 0.25,
+    }
+  }
+}
+
+impl Sun {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(Sun::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "color1" => shader.color1 = param.color()?,
+        "color2" => shader.color2 = param.color()?,
+        "color3" => shader.color3 = param.color()?,
+        "color4" => shader.color4 = param.color()?,
+        "granulation" => shader.granulation = param.float()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for Sun {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    // Coordenadas del fragmento normalizadas al rango [-1, 1]
     let x = fragment.vertex_position.x;
     let y = fragment.vertex_position.y;
-    let time = uniforms.time as f32 * 0.05;
+    let center = (0.0, 0.0); // Centro del degradado
+    let radius = ((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt(); // Distancia al centro
+
+    // Los bordes entre gránulos se oscurecen y se mueven lentamente
+    let time = uniforms.time as f32 * 0.0005;
+    let granules = SUN_GRANULES.get01(&(fragment.vertex_position + Vec3::new(time, time, 0.0)));
+
+    let t = (radius + (1.0 - granules) * self.granulation).clamp(0.0, 1.0); // Radio normalizado entre 0 y 1
+    let color = if t < 0.53 {
+        // Mezcla entre el color1 y color2
+        self.color1.lerp(&self.color2, t / 0.33)
+    } else if t < 0.66 {
+        // Mezcla entre el color2 y color3
+        self.color2.lerp(&self.color3, (t - 0.33) / 0.33)
+    } else {
+        // Mezcla entre el color3 y color4
+        self.color3.lerp(&self.color4, (t - 0.66) / 0.34)
+    };
+
+    Surface::emissive(fragment, color)
+  }
+}
+
 
-    // Crear un patrón basado en ondas para un efecto gaseoso dinámico
-    let pattern1 = ((x * zoom + time).sin() * (y * zoom + time).cos()).abs();
-    let pattern2 = ((x * zoom * 0.5 - time).cos() * (y * zoom * 0.7 + time).sin()).abs();
-    let combined_pattern = (pattern1 + pattern2 * 0.5).min(1.0);
+// Coordenadas UV [0, 1] de un punto de la esfera a partir de su posición en el modelo
+fn spherical_uv(position: &Vec3) -> Vec2 {
+  let theta = (position.y / 0.5).clamp(-1.0, 1.0).asin(); // Latitud
+  let phi = position.z.atan2(position.x);                  // Longitud
+  let u = (phi / (2.0 * PI)) + 0.5; // Coordenada u [0, 1]
+  let v = (theta / PI) + 0.5;      // Coordenada v [0, 1]
+
+  Vec2::new(u, v)
+}
+
+// Lleva una normal del espacio tangente (x a lo largo de u, y a lo largo de v, z hacia
+// afuera de la superficie) al mundo. Es lo que guarda cada texel de un mapa de normales.
+fn perturb_normal(fragment: &Fragment, tangent_normal: &Vec3) -> Vec3 {
+  let normal = fragment.normal.normalize();
+  let tangent = fragment.tangent - normal * normal.dot(&fragment.tangent);
+  if tangent.magnitude() <= f32::EPSILON {
+    return normal;
+  }
+  let tangent = tangent.normalize();
+
+  // La bitangente conserva la orientación del mapeo de textura
+  let bitangent = normal.cross(&tangent);
+  let bitangent = if bitangent.dot(&fragment.bitangent) < 0.0 { -bitangent } else { bitangent };
+
+  (tangent * tangent_normal.x + bitangent * tangent_normal.y + normal * tangent_normal.z).normalize()
+}
+
+// Normal leída de un mapa de normales: cada texel guarda en RGB una normal del espacio
+// tangente llevada de [-1, 1] a [0, 255]
+fn normal_from_map(fragment: &Fragment, texture: &Texture, sampler: &Sampler, uv: Vec2) -> Vec3 {
+  let texel = sampler.sample_grad(texture, uv, fragment.tex_coords_dx, fragment.tex_coords_dy);
+  let (r, g, b) = texel.to_float();
+  perturb_normal(fragment, &(Vec3::new(r, g, b) * 2.0 - Vec3::repeat(1.0)))
+}
+
+// Relieve a partir de una función de altura sobre la posición del objeto. La pendiente
+// se mide a lo largo de la tangente y la bitangente con diferencias finitas.
+fn bump_normal<H: Fn(&Vec3) -> f32>(fragment: &Fragment, uniforms: &Uniforms, height: H) -> Vec3 {
+  let step = 0.001;
+
+  // El marco tangente del fragmento está en el mundo; la altura se evalúa en el objeto
+  let to_object = mat4_to_mat3(&uniforms.model_matrix).try_inverse().unwrap_or(Mat3::identity());
+  let tangent = (to_object * fragment.tangent).normalize();
+  let bitangent = (to_object * fragment.bitangent).normalize();
+
+  let position = fragment.vertex_position;
+  let center = height(&position);
+  let slope_u = (height(&(position + tangent * step)) - center) / step;
+  let slope_v = (height(&(position + bitangent * step)) - center) / step;
+
+  perturb_normal(fragment, &Vec3::new(-slope_u, -slope_v, 1.0))
+}
+
+// Carga una textura para un shader; el error lleva la ruta y llega a la línea de la escena
+fn load_texture(path: &str) -> Result<Texture, String> {
+  Texture::load(path).map_err(|error| format!("could not load `{}`: {}", path, error))
+}
+
+const EARTH_TEXTURE: &str = "assets/textures/earth.jpg";
+
+// Se repite alrededor del ecuador y se detiene en los polos. El filtro anisotrópico
+// evita que el mapa se emborrone hacia el borde de la esfera.
+const EARTH_SAMPLER: Sampler = Sampler::trilinear(Wrap::Repeat)
+  .with_wrap(Wrap::Repeat, Wrap::Clamp)
+  .with_anisotropy(4);
+
+// La tierra con su mapa; no tiene parámetros
+pub struct Earth {
+  texture: Texture,
+}
+
+impl Earth {
+  // El mapa se carga al crear el shader, y un error llega a la escena en vez de
+  // detener el render
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    let texture = load_texture(EARTH_TEXTURE)?;
+    with_params(Earth { texture }, params, |_, param| Err(param.unknown()))
+  }
+}
+
+impl FragmentShader for Earth {
+  fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+    // Las coordenadas de textura de sphere.obj se repiten en cada cara, así que se
+    // proyecta la posición; la fila 0 de la imagen es el polo norte
+    let earth_uv = |position: &Vec3| {
+      let uv = spherical_uv(position);
+      Vec2::new(uv.x, 1.0 - uv.y)
+    };
+    let uv = earth_uv(&fragment.vertex_position);
+
+    // Derivadas en pantalla de la proyección, a partir de las de la posición en el quad.
+    // Al cruzar la costura la u salta de 1 a 0, así que se toma el camino corto.
+    let derivative = |delta: &Vec3| {
+      let d = earth_uv(&(fragment.vertex_position + delta)) - uv;
+      Vec2::new(d.x - d.x.round(), d.y)
+    };
+    let uv_dx = derivative(&fragment.vertex_position_dx);
+    let uv_dy = derivative(&fragment.vertex_position_dy);
+
+    let color = EARTH_SAMPLER.sample_grad(&self.texture, uv, uv_dx, uv_dy);
+    Surface::new(fragment, color)
+  }
+}
+
+// Nubes arremolinadas de la tierra
+static CLOUDS: Lazy<Noise> = Lazy::new(|| {
+  Noise::new(NoiseKind::Simplex, 31).frequency(4.0).fbm(5).warp(0.2, 1.5)
+});
+
+// Capa de nubes de la tierra, dibujada como una esfera translúcida sobre el planeta
+pub struct Clouds {
+  pub color: Color,
+  // Radianes que giran las nubes por cuadro
+  pub speed: f32,
+  // Opacidad de las nubes más densas
+  pub max_opacity: f32,
+}
+
+impl Default for Clouds {
+  fn default() -> Self {
+    Clouds {
+      color: Color::new(255, 255, 255),
+      speed: 0.002,
+      max_opacity: 0.8,
+    }
+  }
+}
+
+impl Clouds {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(Clouds::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "color" => shader.color = param.color()?,
+        "speed" => shader.speed = param.float()?,
+        "max_opacity" => shader.max_opacity = param.float()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for Clouds {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    // Las nubes giran despacio alrededor del eje del planeta
+    let angle = uniforms.time as f32 * self.speed;
+    let (sin, cos) = angle.sin_cos();
+    let p = fragment.vertex_position;
+    let position = Vec3::new(p.x * cos - p.z * sin, p.y, p.x * sin + p.z * cos);
+
+    let cloud_pattern = CLOUDS.get01(&position);
+    let opacity = ((cloud_pattern - 0.5) * 2.2).clamp(0.0, self.max_opacity); // Opacidad de las nubes
+
+    // Las nubes nunca quedan del todo a oscuras
+    Surface::new(fragment, (self.color * 0.6).with_alpha(opacity)).with_emissive(self.color * 0.4)
+  }
+}
+
+// Gas que se arremolina en el planeta azul
+static VIBRANT_GAS: Lazy<Noise> = Lazy::new(|| {
+  Noise::new(NoiseKind::Simplex, 41).frequency(2.5).fbm(3).warp(0.3, 1.2)
+});
+
+// Planeta azul de gas disperso
+pub struct VibrantGas {
+  pub speed: f32,
+  // Ajuste de iluminación ambiental para un aspecto de gas disperso
+  pub ambient_color: Color,
+  pub ambient_intensity: f32,
+}
+
+impl Default for VibrantGas {
+  fn default() -> Self {
+    VibrantGas {
+      speed: 0.005,
+      ambient_color: Color::new(200, 80, 198),
+      ambient_intensity: 0.5,
+    }
+  }
+}
+
+impl VibrantGas {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(VibrantGas::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "speed" => shader.speed = param.float()?,
+        "ambient_color" => shader.ambient_color = param.color()?,
+        "ambient_intensity" => shader.ambient_intensity = param.float()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for VibrantGas {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    let time = uniforms.time as f32 * self.speed;
+
+    // Patrón de ruido que fluye para un efecto gaseoso dinámico
+    let flow = Vec3::new(time, time * 0.5, 0.0);
+    let combined_pattern = VIBRANT_GAS.get01(&(fragment.vertex_position + flow));
 
     // Colores de base con tonos más azulados y menos fucsia
     let r = (combined_pattern * 100.0) as u8;
@@ -188,111 +421,426 @@ pub fn vibrant_blue_planet_shader(fragment: &Fragment, uniforms: &Uniforms) -> C
 
     let base_color = Color::new(r, g, b);
 
-    // Ajuste de iluminación ambiental para un aspecto de gas disperso
-    let ambient_intensity = 0.5;
-    let ambient_color = Color::new(200, 80, 198);
-
     // Mezcla del color base y el color ambiental para dar una apariencia gaseosa en toda la superficie
-    base_color * fragment.intensity + ambient_color * ambient_intensity
+    Surface::new(fragment, base_color).with_emissive(self.ambient_color * self.ambient_intensity)
+  }
+}
+
+// Terreno del planeta rocoso y las crestas de su relieve
+static ROCKY_TERRAIN: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Perlin, 51).frequency(6.0).fbm(5));
+static ROCKY_RIDGES: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Simplex, 52).frequency(5.0).ridged(4));
+
+pub struct RockyPlanet {
+  // Colores base para las regiones del terreno
+  pub base_color: Color,      // Color terroso
+  pub highlight_color: Color, // Tonos más claros para áreas elevadas
+  pub light_adjust: f32,      // Ajuste de intensidad lumínica ambiental
+  // Iluminación ambiental simple
+  pub ambient_color: Color,   // Luz cálida difusa
+  pub ambient_intensity: f32,
 }
 
-fn rocky_planet_shader(fragment: &Fragment, _uniforms: &Uniforms) -> Color {
-  let scale = 10.0; // Escala del patrón de ruido
-  let light_adjust = 0.6; // Ajuste de intensidad lumínica ambiental
+impl Default for RockyPlanet {
+  fn default() -> Self {
+    RockyPlanet {
+      base_color: Color::new(120, 85, 60),
+      highlight_color: Color::new(200, 170, 140),
+      light_adjust: 0.6,
+      ambient_color: Color::new(50, 30, 20),
+      ambient_intensity: 0.8,
+    }
+  }
+}
 
-  // Coordenadas esféricas del fragmento
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let z = fragment.vertex_position.z;
+impl RockyPlanet {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(RockyPlanet::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "base_color" => shader.base_color = param.color()?,
+        "highlight_color" => shader.highlight_color = param.color()?,
+        "light_adjust" => shader.light_adjust = param.float()?,
+        "ambient_color" => shader.ambient_color = param.color()?,
+        "ambient_intensity" => shader.ambient_intensity = param.float()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
 
-  // Introducir pseudoaleatoriedad en la textura
-  let randomness = (x * 12.9898 + y * 78.233 + z * 37.719).sin() * 43758.5453;
-  let random_factor = randomness.fract(); // Tomamos solo la parte decimal
+impl FragmentShader for RockyPlanet {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    // Variaciones rocosas a partir de la posición en el modelo
+    let noise_pattern = ROCKY_TERRAIN.get01(&fragment.vertex_position);
 
-  // Coordenadas ajustadas con ruido aleatorio
-  let adjusted_x = x + random_factor * 0.1;
-  let adjusted_y = y + random_factor * 0.1;
+    // Combina colores en función del patrón de ruido
+    let surface_color = self.base_color.lerp(&self.highlight_color, noise_pattern);
 
-  // Patrón de ruido basado en seno y coseno para variaciones rocosas
-  let noise_pattern = ((adjusted_x * scale).sin() * (adjusted_y * scale).cos()).abs();
+    // Relieve de crestas sobre la superficie
+    let normal = bump_normal(fragment, uniforms, ridge_height);
 
-  // Colores base para las regiones del terreno
-  let base_color = Color::new(120, 85, 60); // Color terroso
-  let highlight_color = Color::new(200, 170, 140); // Tonos más claros para áreas elevadas
+    // Ajuste de la intensidad de luz
+    Surface { normal, ..Surface::new(fragment, surface_color * self.light_adjust) }
+        .with_emissive(self.ambient_color * self.ambient_intensity)
+  }
+}
 
-  // Combina colores en función del patrón de ruido
-  let surface_color = base_color.lerp(&highlight_color, noise_pattern);
+// Crestas del planeta rocoso, en unidades del objeto
+fn ridge_height(position: &Vec3) -> f32 {
+  ROCKY_RIDGES.get01(position) * 0.006
+}
 
-  // Iluminación ambiental simple
-  let ambient_intensity = 0.8;
-  let ambient_color = Color::new(50, 30, 20); // Luz cálida difusa
+// Mares oscuros de la luna y sus cráteres. Las noises celulares comparten semilla y
+// frecuencia, así que hablan de las mismas celdas.
+static MOON_MARIA: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Value, 61).frequency(3.0).fbm(4).warp(0.1, 2.0));
+static MOON_DETAIL: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Perlin, 62).frequency(24.0).fbm(3));
+static CRATER_DISTANCE: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Cellular, 63).frequency(9.0));
+static CRATER_EDGE: Lazy<Noise> = Lazy::new(|| {
+  Noise::new(NoiseKind::Cellular, 63).frequency(9.0).cellular_return(CellularReturnType::Distance2Sub)
+});
+static CRATER_CELL: Lazy<Noise> = Lazy::new(|| {
+  Noise::new(NoiseKind::Cellular, 63).frequency(9.0).cellular_return(CellularReturnType::CellValue)
+});
+
+// Colores base para la superficie lunar
+pub struct Moon {
+  pub color1: Color,
+  pub color2: Color,
+  // Color de los mares
+  pub color3: Color,
+}
 
-  // Ajuste de la intensidad de luz
-  let final_color = surface_color * fragment.intensity * light_adjust
-      + ambient_color * ambient_intensity;
+impl Default for Moon {
+  fn default() -> Self {
+    Moon {
+      color1: Color::new(197, 199, 200),
+      color2: Color::new(220, 221, 222),
+      color3: Color::new(137, 149, 154),
+    }
+  }
+}
 
-  final_color
+impl Moon {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(Moon::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "color1" => shader.color1 = param.color()?,
+        "color2" => shader.color2 = param.color()?,
+        "color3" => shader.color3 = param.color()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
 }
 
-fn moon_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  // Colores base para la superficie lunar
-  let color1 = Color::new( 197, 199, 200);
-  let color2 = Color::new( 220, 221, 222);
-  let color3 = Color::new(137, 149, 154);
+impl FragmentShader for Moon {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    let position = fragment.vertex_position;
+    let detail = MOON_DETAIL.get01(&position);
+    let maria = ((MOON_MARIA.get01(&position) - 0.45) * 4.0).clamp(0.0, 1.0);
 
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let frequency = 10.0;
+    let final_color = self.color2.lerp(&self.color1, detail).lerp(&self.color3, maria);
 
-  let wave1= (x*7.0*frequency + y * 5.0 * frequency).sin() * 0.5 + 0.5;
-  let wave2= (x*5.0*frequency - y * 8.0 * frequency + PI / 3.0).sin() * 0.5 + 0.5;
-  let wave3= (x*6.0*frequency + x * 4.0 * frequency + 2.0 * PI/3.0).sin() * 0.5 + 0.5;
+    let normal = bump_normal(fragment, uniforms, crater_height);
+    Surface { normal, ..Surface::new(fragment, final_color) }
+  }
+}
+
+// Cráteres en unidades del objeto: las celdas de una noise celular pueden tener un
+// cráter de tamaño pseudoaleatorio; cerca de su punto la superficie se hunde y
+// alrededor queda un borde elevado, que se desvanece al llegar a la celda vecina
+fn crater_height(position: &Vec3) -> f32 {
+  // Casi la mitad de las celdas no tiene cráter
+  let cell = CRATER_CELL.get01(position);
+  if cell < 0.45 {
+    return 0.0;
+  }
+  let radius = 0.15 + 0.3 * ((cell - 0.45) / 0.55).powi(2);
 
-  let mut final_color = color1.lerp(&color2, wave1);
-  final_color = final_color.lerp(&color3, wave2);
-  final_color = final_color.lerp(&color1, wave3);
+  // Distancia al punto de la celda, relativa al radio del cráter
+  let distance = (CRATER_DISTANCE.get(position) + 1.0) / radius;
+  // Diferencia entre las distancias a los dos puntos más cercanos, nula en el borde de la celda
+  let edge = ((CRATER_EDGE.get(position) + 1.0) / 0.15).clamp(0.0, 1.0);
 
-  final_color * fragment.intensity
+  let bowl = if distance < 1.0 { distance * distance - 1.0 } else { 0.0 };
+  let rim = 0.35 * (-((distance - 1.0) / 0.2).powi(2)).exp();
+  (bowl + rim) * edge * 0.004
 }
 
+// Bandas irregulares del planeta con anillos
+static RING_PLANET_BANDS: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Perlin, 71).frequency(5.0).fbm(4));
 
-fn ring_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  // Colores base para la superficie lunar
-  let base_color = Color::new(184, 162, 42 ); // Gris claro para la superficie
-  let crater_color = Color::new(184, 177, 42 ); // Gris oscuro para los cráteres
+// Planeta 7, el de los anillos
+pub struct RingedPlanet {
+  pub base_color: Color,
+  pub band_color: Color,
+  // Sombra suave: el lado oscuro nunca queda negro del todo
+  pub shadow_color: Color,
+}
 
-  // Coordenadas del fragmento
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let z = fragment.vertex_position.z;
+impl Default for RingedPlanet {
+  fn default() -> Self {
+    RingedPlanet {
+      base_color: Color::new(184, 162, 42),
+      band_color: Color::new(184, 177, 42),
+      shadow_color: Color::new(50, 50, 50),
+    }
+  }
+}
+
+impl RingedPlanet {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(RingedPlanet::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "base_color" => shader.base_color = param.color()?,
+        "band_color" => shader.band_color = param.color()?,
+        "shadow_color" => shader.shadow_color = param.color()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for RingedPlanet {
+  fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+    // Ruido estirado a lo largo del ecuador, como las bandas de un gigante gaseoso
+    let p = fragment.vertex_position;
+    let noise_value = RING_PLANET_BANDS.get01(&Vec3::new(p.x * 0.4, p.y * 3.0, p.z * 0.4));
 
-  // Coordenadas esféricas para la superficie
-  let longitude = z.atan2(x); // Longitud
-  let latitude = y.asin();    // Latitud
+    // Interpolación de colores entre las bandas y la superficie base
+    let surface_color = self.base_color.lerp(&self.band_color, ((noise_value - 0.4) * 5.0).clamp(0.0, 1.0));
 
-  // Ruido para cráteres
-  let scale = 10.0; // Escala para los patrones de ruido
-  let noise_value = ((longitude * scale).cos() * (latitude * scale).sin()).abs();
+    Surface::new(fragment, surface_color).with_emissive(self.shadow_color * 0.2)
+  }
+}
 
-  // Generar cráteres (regiones más oscuras)
-  let crater_threshold = 0.4;
-  let is_crater = noise_value > crater_threshold;
+// Densidad de los anillos según la distancia al planeta
+static RING_DENSITY: Lazy<Noise> = Lazy::new(|| Noise::new(NoiseKind::Value, 81).frequency(30.0).fbm(4));
 
-  // Interpolación de colores entre cráteres y la superficie base
-  let surface_color = if is_crater {
-      crater_color
-  } else {
-      base_color
-  };
+// Anillos translúcidos del planeta 7, con bandas de distinta opacidad
+pub struct PlanetRings {
+  pub inner_radius: f32, // Hueco entre el planeta y los anillos
+  pub outer_radius: f32,
+  pub light_color: Color,
+  pub dark_color: Color,
+}
 
-  // Iluminación básica para simular sombras
-  let light_direction = Vec3::new(1.0, 1.0, 1.0).normalize(); // Dirección de la luz
-  let normal = fragment.vertex_position.normalize(); // Normal de la esfera
-  let light_intensity = (normal.dot(&light_direction)).clamp(0.2, 1.0); // Intensidad de la luz
-  let shadow_color = Color::new(50, 50, 50); // Sombra suave
+impl Default for PlanetRings {
+  fn default() -> Self {
+    PlanetRings {
+      inner_radius: 1.6,
+      outer_radius: 2.87,
+      light_color: Color::new(226, 205, 150),
+      dark_color: Color::new(150, 120, 70),
+    }
+  }
+}
 
-  // Aplicar iluminación
-  let final_color = surface_color * light_intensity + shadow_color * (1.0 - light_intensity);
+impl PlanetRings {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(PlanetRings::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "inner_radius" => shader.inner_radius = param.float()?,
+        "outer_radius" => shader.outer_radius = param.float()?,
+        "light_color" => shader.light_color = param.color()?,
+        "dark_color" => shader.dark_color = param.color()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for PlanetRings {
+  fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+    let radius = Vec2::new(fragment.vertex_position.x, fragment.vertex_position.z).norm();
+    if radius < self.inner_radius {
+      return Surface::new(fragment, Color::black().with_alpha(0.0));
+    }
+
+    // Bandas concéntricas: la noise solo depende del radio
+    let t = (radius - self.inner_radius) / (self.outer_radius - self.inner_radius);
+    let bands = RING_DENSITY.get01(&Vec3::new(t, 0.0, 0.0));
+
+    let band_color = self.dark_color.lerp(&self.light_color, bands);
+
+    // Más transparentes hacia los bordes
+    let edge_fade = (t * (1.0 - t) * 6.0).clamp(0.0, 1.0);
+    Surface::new(fragment, band_color.with_alpha((0.25 + 0.6 * bands) * edge_fade))
+  }
+}
+
+// Color plano para el contorno de selección
+pub struct Outline {
+  pub color: Color,
+}
 
-  final_color
+impl Default for Outline {
+  fn default() -> Self {
+    Outline { color: Color::new(255, 196, 0) }
+  }
+}
+
+impl Outline {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(Outline::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "color" => shader.color = param.color()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for Outline {
+  fn shade(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Surface {
+    Surface::emissive(fragment, self.color)
+  }
+}
+
+// La imagen de la cámara se estira hasta el marco
+const MONITOR_SAMPLER: Sampler = Sampler::trilinear(Wrap::Clamp);
+
+// Pantalla que muestra la textura de una cámara fuera de pantalla
+pub struct Monitor {
+  pub frame_color: Color,
+  // Marco alrededor de la imagen, en coordenadas de textura
+  pub border: f32,
+}
+
+impl Default for Monitor {
+  fn default() -> Self {
+    Monitor {
+      frame_color: Color::new(40, 40, 48),
+      border: 0.04,
+    }
+  }
+}
+
+impl Monitor {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(Monitor::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "frame_color" => shader.frame_color = param.color()?,
+        "border" => shader.border = param.float()?,
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for Monitor {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    Surface::emissive(fragment, self.image(fragment, uniforms))
+  }
+}
+
+impl Monitor {
+  fn image(&self, fragment: &Fragment, uniforms: &Uniforms) -> Color {
+    let uv = fragment.tex_coords;
+    let border = self.border;
+    if uv.x < border || uv.x > 1.0 - border || uv.y < border || uv.y > 1.0 - border {
+      return self.frame_color;
+    }
+
+    let Some(texture) = &uniforms.texture else {
+      return Color::black();
+    };
+
+    // Reescalar las coordenadas al área dentro del marco
+    let scale = 1.0 / (1.0 - 2.0 * border);
+    let inner = (uv - Vec2::new(border, border)) * scale;
+    let image = MONITOR_SAMPLER.sample_grad(texture, inner, fragment.tex_coords_dx * scale, fragment.tex_coords_dy * scale);
+
+    // Líneas de barrido suaves
+    let scanline = 0.85 + 0.15 * (inner.y * texture.height as f32 * PI).sin().abs();
+    image * scanline
+  }
+}
+
+// Los mapas de normales siguen las coordenadas de textura de la malla
+const NORMAL_MAP_SAMPLER: Sampler = Sampler::trilinear(Wrap::Repeat);
+
+// Material del MTL de la malla, iluminado con Blinn-Phong. Los colores vienen del MTL;
+// el único parámetro es un mapa de normales opcional (`normal_map <ruta>`).
+#[derive(Default)]
+pub struct BlinnPhong {
+  pub normal_map: Option<Texture>,
+}
+
+impl BlinnPhong {
+  pub fn from_params(params: &[Param]) -> Result<Box<dyn FragmentShader>, String> {
+    with_params(BlinnPhong::default(), params, |shader, param| {
+      match param.name.as_str() {
+        "normal_map" => shader.normal_map = Some(load_texture(param.path()?)?),
+        _ => return Err(param.unknown()),
+      }
+      Ok(())
+    })
+  }
+}
+
+impl FragmentShader for BlinnPhong {
+  fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> Surface {
+    let material = &uniforms.material;
+    let view = (uniforms.camera_position - fragment.world_position).normalize();
+
+    let normal = match &self.normal_map {
+      Some(map) => normal_from_map(fragment, map, &NORMAL_MAP_SAMPLER, fragment.tex_coords),
+      None => fragment.normal,
+    };
+
+    // Iluminación de dos caras: la normal siempre mira hacia la cámara
+    let normal = if fragment.normal.dot(&view) < 0.0 { -normal } else { normal };
+
+    // Reflejo del entorno en ángulos rasantes (Fresnel de Schlick a partir de Ni)
+    let f0 = material.base_reflectance();
+    let fresnel = f0 + (1.0 - f0) * (1.0 - normal.dot(&view).max(0.0)).powi(5);
+
+    Surface { normal, ..Surface::new(fragment, material.diffuse) }
+      .with_ambient(material.ambient)
+      .with_specular(material.specular, material.shininess)
+      .with_emissive(uniforms.ambient * fresnel)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fragment() -> Fragment {
+    Fragment {
+      position: Vec2::zeros(),
+      color: Color::black(),
+      depth: 0.0,
+      normal: Vec3::new(0.0, 0.0, 1.0),
+      tangent: Vec3::new(1.0, 0.0, 0.0),
+      bitangent: Vec3::new(0.0, 1.0, 0.0),
+      vertex_position: Vec3::zeros(),
+      world_position: Vec3::zeros(),
+      tex_coords: Vec2::zeros(),
+      tex_coords_dx: Vec2::zeros(),
+      tex_coords_dy: Vec2::zeros(),
+      vertex_position_dx: Vec3::zeros(),
+      vertex_position_dy: Vec3::zeros(),
+    }
+  }
+
+  #[test]
+  fn normal_maps_decode_into_the_tangent_frame() {
+    let sampler = Sampler::new(crate::texture::Filter::Nearest, Wrap::Repeat);
+    let normal = |texel: Color| {
+      let texture = Texture::new(1, 1, vec![texel]);
+      normal_from_map(&fragment(), &texture, &sampler, Vec2::new(0.5, 0.5))
+    };
+
+    // Un mapa plano deja la normal geométrica; uno inclinado la lleva hacia la tangente
+    assert!((normal(Color::new(128, 128, 255)) - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 0.01);
+    let tilted = Vec3::new(1.0, 0.0, 1.0).normalize();
+    assert!((normal(Color::new(255, 128, 255)) - tilted).magnitude() < 0.01);
+  }
 }
@@ -0,0 +1,183 @@
+use nalgebra_glm::{look_at, ortho, perspective, Mat4, Vec3, Vec4};
+use std::f32::consts::PI;
+use crate::framebuffer::Framebuffer;
+
+// How shadow maps are rendered and filtered
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    // Width and height of each depth map, in texels
+    pub resolution: usize,
+    // Taken off the depth of the receiver before comparing, in texels of the map at the
+    // receiver, and grown on surfaces at a grazing angle to the light. Texels of a
+    // perspective map get wider with distance, so no fixed depth offset fits them.
+    pub depth_bias: f32,
+    // The receiver is also pushed this far along its normal, in world units
+    pub normal_offset: f32,
+    // Percentage-closer filtering over a (2r + 1)^2 texel kernel; 0 is a single hard lookup
+    pub pcf_radius: i32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 512,
+            depth_bias: 1.5,
+            normal_offset: 0.01,
+            pcf_radius: 1,
+        }
+    }
+}
+
+// Cosine below which the slope of a receiver stops growing its bias
+const MIN_COS: f32 = 0.2;
+
+// Depth of the closest caster seen from a light, for a single projection. Depths are
+// kept as distances along the view direction of the light, which are linear in world
+// units for orthographic and perspective maps alike.
+pub struct DepthMap {
+    pub size: usize,
+    pub view_matrix: Mat4,
+    pub projection_matrix: Mat4,
+    depth: Vec<f32>,
+}
+
+impl DepthMap {
+    // Keeps the depth buffer of a framebuffer that was rendered with these matrices
+    pub fn from_framebuffer(framebuffer: &Framebuffer, view_matrix: Mat4, projection_matrix: Mat4) -> Self {
+        let inverse = projection_matrix.try_inverse().unwrap_or_else(Mat4::identity);
+        let depth = framebuffer
+            .depths()
+            .into_iter()
+            .map(|z| {
+                if z.is_infinite() {
+                    return z;
+                }
+                let view = inverse * Vec4::new(0.0, 0.0, z, 1.0);
+                -view.z / view.w
+            })
+            .collect();
+
+        DepthMap {
+            size: framebuffer.width,
+            view_matrix,
+            projection_matrix,
+            depth,
+        }
+    }
+
+    // Fraction of the PCF kernel around the point that is not occluded, or None when
+    // the point falls outside of the map
+    fn visibility(&self, position: &Vec3, normal: &Vec3, settings: &ShadowSettings) -> Option<f32> {
+        let view = self.view_matrix * Vec4::new(position.x, position.y, position.z, 1.0);
+        let clip = self.projection_matrix * view;
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.xyz() / clip.w;
+        if ndc.x.abs() > 1.0 || ndc.y.abs() > 1.0 || ndc.z > 1.0 {
+            return None;
+        }
+
+        // Same mapping as the viewport matrix the map was rendered with
+        let size = self.size as i32;
+        let x = ((ndc.x + 1.0) * 0.5 * self.size as f32) as i32;
+        let y = ((1.0 - ndc.y) * 0.5 * self.size as f32) as i32;
+        // The map spans 2 w / P00 world units across at the receiver: w is the depth in
+        // a perspective map and 1 in an orthographic one
+        let texel = 2.0 * clip.w / (self.projection_matrix[(0, 0)] * self.size as f32);
+
+        // Surfaces at a grazing angle to the light change depth faster from one texel to
+        // the next. Perspective maps look out from the light, orthographic ones along -z.
+        let to_light = if self.projection_matrix[(3, 3)] == 0.0 { -view.xyz().normalize() } else { Vec3::z() };
+        let cos_theta = (self.view_matrix * Vec4::new(normal.x, normal.y, normal.z, 0.0)).xyz().normalize().dot(&to_light);
+        let slope = (1.0 - cos_theta * cos_theta).max(0.0).sqrt() / cos_theta.max(MIN_COS);
+        let depth = -view.z - settings.depth_bias * texel * (1.0 + slope);
+
+        let radius = settings.pcf_radius;
+        let mut lit = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let tx = (x + dx).clamp(0, size - 1) as usize;
+                let ty = (y + dy).clamp(0, size - 1) as usize;
+                if depth <= self.depth[ty * self.size + tx] {
+                    lit += 1;
+                }
+            }
+        }
+
+        let taps = (2 * radius + 1).pow(2);
+        Some(lit as f32 / taps as f32)
+    }
+}
+
+// Shadow of one light, looked up from fragment shaders and the lighting pass
+pub enum ShadowMap {
+    // Orthographic map along the direction of a directional light
+    Directional(DepthMap, ShadowSettings),
+    // One perspective map per cube face around a point light, indexed by `cube_face`
+    Point(Vec3, Vec<DepthMap>, ShadowSettings),
+}
+
+impl ShadowMap {
+    // How much of the light reaches a world-space point: 1 lit, 0 fully in shadow
+    pub fn visibility(&self, position: &Vec3, normal: &Vec3) -> f32 {
+        match self {
+            ShadowMap::Directional(map, settings) => {
+                let position = position + normal.normalize() * settings.normal_offset;
+                map.visibility(&position, normal, settings).unwrap_or(1.0)
+            }
+            ShadowMap::Point(light_position, faces, settings) => {
+                let position = position + normal.normalize() * settings.normal_offset;
+                faces[cube_face(&(position - light_position))]
+                    .visibility(&position, normal, settings)
+                    .unwrap_or(1.0)
+            }
+        }
+    }
+}
+
+// Directions and up vectors of the cube faces, in the usual +x, -x, +y, -y, +z, -z order
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+// Face of the cube map that a direction from its center goes through
+fn cube_face(direction: &Vec3) -> usize {
+    let abs = direction.abs();
+    if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x > 0.0 { 0 } else { 1 }
+    } else if abs.y >= abs.z {
+        if direction.y > 0.0 { 2 } else { 3 }
+    } else if direction.z > 0.0 {
+        4
+    } else {
+        5
+    }
+}
+
+// View and projection of a directional light covering a bounding sphere of the casters
+pub fn directional_light_matrices(direction: &Vec3, center: &Vec3, radius: f32) -> (Mat4, Mat4) {
+    let direction = direction.normalize();
+    let eye = center - direction * (radius * 2.0);
+    let up = if direction.y.abs() > 0.99 { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+
+    let view = look_at(&eye, center, &up);
+    let projection = ortho(-radius, radius, -radius, radius, radius * 0.5, radius * 3.5);
+    (view, projection)
+}
+
+// Views and projection of the six cube faces around a point light
+pub fn point_light_matrices(position: &Vec3, far: f32) -> ([Mat4; 6], Mat4) {
+    let views = CUBE_FACES.map(|(direction, up)| {
+        let direction = Vec3::from(direction);
+        look_at(position, &(position + direction), &Vec3::from(up))
+    });
+    // 90 degrees per face so the six frustums tile the whole sphere
+    let projection = perspective(1.0, PI / 2.0, far * 0.001, far);
+    (views, projection)
+}
@@ -0,0 +1,58 @@
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::fragment::Fragment;
+
+// Material ID of G-buffer pixels that no surface was written to
+pub const NO_MATERIAL: u8 = u8::MAX;
+
+// What a fragment shader knows about a point of a surface before any light is
+// applied. The forward path lights it right away; the deferred path stores it
+// in the G-buffer and lights every pixel in a single pass.
+#[derive(Clone, Copy, Debug)]
+pub struct Surface {
+    // Color reflected under white light; its alpha is the surface opacity
+    pub albedo: Color,
+    // Reflectance for the ambient light, the albedo unless a material says otherwise
+    pub ambient: Color,
+    // Blinn-Phong highlight color and exponent; black surfaces have no highlight
+    pub specular: Color,
+    pub shininess: f32,
+    // Light given off by the surface itself, added regardless of the lights
+    pub emissive: Color,
+    // World-space normal and position
+    pub normal: Vec3,
+    pub position: Vec3,
+    pub material: u8,
+}
+
+impl Surface {
+    pub fn new(fragment: &Fragment, albedo: Color) -> Self {
+        Surface {
+            albedo,
+            ambient: albedo,
+            specular: Color::black(),
+            shininess: 1.0,
+            emissive: Color::black(),
+            normal: fragment.normal,
+            position: fragment.world_position,
+            material: NO_MATERIAL,
+        }
+    }
+
+    // A surface that only emits light, like the sun or a screen
+    pub fn emissive(fragment: &Fragment, color: Color) -> Self {
+        Surface::new(fragment, Color::black()).with_emissive(color)
+    }
+
+    pub fn with_emissive(self, emissive: Color) -> Self {
+        Surface { emissive, ..self }
+    }
+
+    pub fn with_ambient(self, ambient: Color) -> Self {
+        Surface { ambient, ..self }
+    }
+
+    pub fn with_specular(self, specular: Color, shininess: f32) -> Self {
+        Surface { specular, shininess, ..self }
+    }
+}
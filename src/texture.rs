@@ -0,0 +1,342 @@
+use std::path::Path;
+use nalgebra_glm::Vec2;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+
+// One level of a mip chain. Texels are stored row by row from the top of the
+// image, so v = 0 is the top edge.
+struct MipLevel {
+    width: usize,
+    height: usize,
+    data: Vec<Color>,
+}
+
+impl MipLevel {
+    fn get(&self, x: usize, y: usize) -> Color {
+        self.data[y * self.width + x]
+    }
+
+    // Box filters the level down to half its size. Along an odd side each texel
+    // covers one and a half source texels, so it is weighted across three of them
+    // and the last row or column still counts.
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let rows = halving_taps(self.height, y);
+            for x in 0..width {
+                let columns = halving_taps(self.width, x);
+                let filtered = rows.map(|(sy, wy)| (weighted(columns.map(|(sx, wx)| (self.get(sx, sy), wx))), wy));
+                data.push(weighted(filtered));
+            }
+        }
+
+        MipLevel { width, height, data }
+    }
+}
+
+// Source texels and their weights for texel `i` of a side of `size` texels being halved
+fn halving_taps(size: usize, i: usize) -> [(usize, f32); 3] {
+    if size == 1 {
+        return [(0, 1.0), (0, 0.0), (0, 0.0)];
+    }
+    if size.is_multiple_of(2) {
+        return [(2 * i, 0.5), (2 * i + 1, 0.5), (2 * i + 1, 0.0)];
+    }
+
+    let (half, side, t) = ((size / 2) as f32, size as f32, i as f32);
+    [(2 * i, (half - t) / side), (2 * i + 1, half / side), (2 * i + 2, (t + 1.0) / side)]
+}
+
+// Weighted average of three colors whose weights add up to 1, alpha included
+fn weighted([(a, wa), (b, wb), (c, wc)]: [(Color, f32); 3]) -> Color {
+    a.lerp(&b, wb / (wa + wb)).lerp(&c, wc)
+}
+
+// An image with its full mip chain, down to a single texel
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    levels: Vec<MipLevel>,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, data: Vec<Color>) -> Self {
+        let mut levels = vec![MipLevel { width, height, data }];
+        while let Some(level) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+            levels.push(level.downsample());
+        }
+
+        Texture { width, height, levels }
+    }
+
+    // Decodes an image file in any format `image` supports, keeping its alpha channel
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.to_rgba8();
+        let data = image.pixels().map(|&image::Rgba([r, g, b, a])| Color::new_rgba(r, g, b, a)).collect();
+
+        Ok(Texture::new(image.width() as usize, image.height() as usize, data))
+    }
+
+    // Copies the resolved colors of a framebuffer, so a rendered image can be sampled by shaders
+    pub fn from_framebuffer(framebuffer: &Framebuffer) -> Self {
+        let data = framebuffer.buffer.iter().map(|&pixel| Color::from_hex(pixel)).collect();
+        Texture::new(framebuffer.width, framebuffer.height, data)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.levels[0].get(x, y)
+    }
+}
+
+// How texels are combined around the sampled point
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    // The single texel the point falls in
+    Nearest,
+    // Weighted average of the four texels closest to the point
+    Bilinear,
+}
+
+// How mip levels are chosen when the texture is minified
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MipFilter {
+    // Always read the full resolution image
+    None,
+    // The level closest to the LOD
+    Nearest,
+    // Blend of the two levels around the LOD; trilinear along with `Filter::Bilinear`
+    Linear,
+}
+
+// What coordinates outside of [0, 1] read
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    // The texture tiles
+    Repeat,
+    // The edge texels stretch outwards
+    Clamp,
+    // The texture tiles, flipped on every other copy
+    Mirror,
+}
+
+impl Wrap {
+    // Brings a texel coordinate back into 0..size
+    fn apply(self, i: i32, size: usize) -> usize {
+        let size = size as i32;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size { i } else { 2 * size - 1 - i }
+            }
+        };
+        i as usize
+    }
+}
+
+// Filtering and wrapping used to read a texture at texture coordinates
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub mip_filter: MipFilter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    // Most taps taken along the footprint of a stretched pixel; 1 turns anisotropic filtering off
+    pub max_anisotropy: u32,
+}
+
+impl Sampler {
+    pub const fn new(filter: Filter, wrap: Wrap) -> Self {
+        Sampler {
+            filter,
+            mip_filter: MipFilter::None,
+            wrap_u: wrap,
+            wrap_v: wrap,
+            max_anisotropy: 1,
+        }
+    }
+
+    // Bilinear within and linear between mip levels
+    pub const fn trilinear(wrap: Wrap) -> Self {
+        Sampler { mip_filter: MipFilter::Linear, ..Sampler::new(Filter::Bilinear, wrap) }
+    }
+
+    pub const fn with_wrap(self, wrap_u: Wrap, wrap_v: Wrap) -> Self {
+        Sampler { wrap_u, wrap_v, ..self }
+    }
+
+    pub const fn with_anisotropy(self, max_anisotropy: u32) -> Self {
+        Sampler { max_anisotropy, ..self }
+    }
+
+    // Reads the full resolution image, for when no derivatives are at hand
+    pub fn sample(&self, texture: &Texture, uv: Vec2) -> Color {
+        self.sample_level(&texture.levels[0], uv)
+    }
+
+    // Reads the texture for a pixel whose texture coordinates change by `uv_dx` and
+    // `uv_dy` to the next pixel, picking the LOD from the size of that footprint
+    pub fn sample_grad(&self, texture: &Texture, uv: Vec2, uv_dx: Vec2, uv_dy: Vec2) -> Color {
+        if self.mip_filter == MipFilter::None {
+            return self.sample(texture, uv);
+        }
+
+        let (major, taps, lod) = self.footprint(texture, uv_dx, uv_dy);
+        (0..taps).fold(Color::black(), |color, i| {
+            let offset = (i as f32 + 0.5) / taps as f32 - 0.5;
+            let tap = self.sample_lod(texture, uv + major * offset, lod);
+            // Running average of the taps so far
+            if i == 0 { tap } else { color.lerp(&tap, 1.0 / (i + 1) as f32) }
+        })
+    }
+
+    // Major axis of the footprint of a pixel, how many taps cover it and the LOD each tap reads
+    fn footprint(&self, texture: &Texture, uv_dx: Vec2, uv_dy: Vec2) -> (Vec2, u32, f32) {
+        // Footprint axes in texels of the full resolution image
+        let size = Vec2::new(texture.width as f32, texture.height as f32);
+        let length_x = uv_dx.component_mul(&size).magnitude();
+        let length_y = uv_dy.component_mul(&size).magnitude();
+        let (major, major_length, minor_length) = if length_x >= length_y {
+            (uv_dx, length_x, length_y)
+        } else {
+            (uv_dy, length_y, length_x)
+        };
+
+        // An anisotropic footprint is covered with several taps along its major axis,
+        // each one reading the level that fits the minor axis
+        let taps = if self.max_anisotropy > 1 && minor_length > 0.0 {
+            ((major_length / minor_length).ceil() as u32).clamp(1, self.max_anisotropy)
+        } else {
+            1
+        };
+        let lod = (major_length / taps as f32).max(f32::EPSILON).log2();
+
+        (major, taps, lod)
+    }
+
+    fn sample_lod(&self, texture: &Texture, uv: Vec2, lod: f32) -> Color {
+        let last = (texture.levels.len() - 1) as f32;
+        let lod = lod.clamp(0.0, last);
+
+        match self.mip_filter {
+            MipFilter::None => self.sample_level(&texture.levels[0], uv),
+            MipFilter::Nearest => self.sample_level(&texture.levels[lod.round() as usize], uv),
+            MipFilter::Linear => {
+                let level = lod.floor() as usize;
+                let fine = self.sample_level(&texture.levels[level], uv);
+                if lod == level as f32 {
+                    return fine;
+                }
+                let coarse = self.sample_level(&texture.levels[level + 1], uv);
+                fine.lerp(&coarse, lod - level as f32)
+            }
+        }
+    }
+
+    fn sample_level(&self, level: &MipLevel, uv: Vec2) -> Color {
+        let texel = |x: i32, y: i32| {
+            level.get(self.wrap_u.apply(x, level.width), self.wrap_v.apply(y, level.height))
+        };
+
+        // Texel centers sit at half-integer coordinates
+        let x = uv.x * level.width as f32;
+        let y = uv.y * level.height as f32;
+
+        match self.filter {
+            Filter::Nearest => texel(x.floor() as i32, y.floor() as i32),
+            Filter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i32, y0 as i32);
+
+                let top = texel(x0, y0).lerp(&texel(x0 + 1, y0), tx);
+                let bottom = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), tx);
+                top.lerp(&bottom, ty)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank(width: usize, height: usize) -> Texture {
+        Texture::new(width, height, vec![Color::black(); width * height])
+    }
+
+    #[test]
+    fn mip_chain_halves_odd_and_non_square_sizes() {
+        let texture = blank(5, 3);
+        let sizes: Vec<(usize, usize)> = texture.levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, vec![(5, 3), (2, 1), (1, 1)]);
+
+        // The white last column is a third of the single texel it filters into
+        let mut data = vec![Color::black(); 3];
+        data[2] = Color::new(255, 255, 255);
+        let texture = Texture::new(3, 1, data);
+        let (r, g, b) = texture.levels[1].get(0, 0).to_float();
+        for channel in [r, g, b] {
+            assert!((channel - 1.0 / 3.0).abs() < 0.01, "{}", channel);
+        }
+
+        // On a 5 texel side each texel takes in the one between them halfway
+        let data = [0, 0, 255, 0, 0].map(|v| Color::new(v, v, v)).to_vec();
+        let level = &Texture::new(5, 1, data).levels[1];
+        for x in 0..2 {
+            let (r, _, _) = level.get(x, 0).to_float();
+            assert!((r - 1.0 / 5.0).abs() < 0.01, "{}", r);
+        }
+    }
+
+    #[test]
+    fn lod_follows_the_footprint_in_texels() {
+        let texture = blank(8, 8);
+        let sampler = Sampler::trilinear(Wrap::Repeat);
+        let texel = 1.0 / 8.0;
+
+        let (_, taps, lod) = sampler.footprint(&texture, Vec2::new(texel, 0.0), Vec2::new(0.0, texel));
+        assert_eq!(taps, 1);
+        assert!(lod.abs() < 1e-5, "{}", lod);
+
+        let (_, _, lod) = sampler.footprint(&texture, Vec2::new(2.0 * texel, 0.0), Vec2::new(0.0, 2.0 * texel));
+        assert!((lod - 1.0).abs() < 1e-5, "{}", lod);
+    }
+
+    #[test]
+    fn anisotropic_taps_are_clamped() {
+        let texture = blank(8, 8);
+        let texel = 1.0 / 8.0;
+        let (uv_dx, uv_dy) = (Vec2::new(16.0 * texel, 0.0), Vec2::new(0.0, texel));
+
+        // A 16:1 footprint takes at most `max_anisotropy` taps, each reading a coarser level
+        let (major, taps, lod) = Sampler::trilinear(Wrap::Repeat).with_anisotropy(4).footprint(&texture, uv_dx, uv_dy);
+        assert_eq!(major, uv_dx);
+        assert_eq!(taps, 4);
+        assert!((lod - 2.0).abs() < 1e-5, "{}", lod);
+
+        let (_, taps, lod) = Sampler::trilinear(Wrap::Repeat).footprint(&texture, uv_dx, uv_dy);
+        assert_eq!(taps, 1);
+        assert!((lod - 4.0).abs() < 1e-5, "{}", lod);
+    }
+
+    #[test]
+    fn wrap_brings_indices_back_into_the_texture() {
+        assert_eq!(Wrap::Repeat.apply(-1, 4), 3);
+        assert_eq!(Wrap::Repeat.apply(5, 4), 1);
+        assert_eq!(Wrap::Clamp.apply(-3, 4), 0);
+        assert_eq!(Wrap::Clamp.apply(9, 4), 3);
+        assert_eq!(Wrap::Mirror.apply(-1, 4), 0);
+        assert_eq!(Wrap::Mirror.apply(4, 4), 3);
+        assert_eq!(Wrap::Mirror.apply(5, 4), 2);
+        assert_eq!(Wrap::Mirror.apply(9, 4), 1);
+    }
+}
@@ -1,69 +1,295 @@
-use nalgebra_glm::{Vec3, dot, Vec2};
+use std::ops::Range;
+use nalgebra_glm::{Vec3, Vec4, Vec2};
 use crate::fragment::Fragment;
 use crate::vertex::{Vertex};
 use crate::color::Color;
+use crate::framebuffer::MAX_SAMPLES;
 
-pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<Fragment> {
-    let mut fragments = Vec::new();
+// How varyings are interpolated across the triangle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    // Barycentrics corrected by 1/w, so attributes are linear in view space
+    Perspective,
+    // Plain screen-space barycentrics, kept around to compare against
+    Affine,
+}
+
+impl Interpolation {
+    pub fn toggle(self) -> Self {
+        match self {
+            Interpolation::Perspective => Interpolation::Affine,
+            Interpolation::Affine => Interpolation::Perspective,
+        }
+    }
+}
+
+// A pixel covered by a triangle. Only position and depth are known at this
+// point, so the depth test can run before any varying is interpolated.
+// `depth` and `barycentric` are taken at the pixel center, `mask` has one bit
+// per covered sample and `sample_depths` the depth at each of those samples.
+// `quad` has the barycentrics at the centers of the 2x2 quad the pixel belongs
+// to, in `QUAD_PIXELS` order. Quad pixels outside of the triangle are
+// extrapolated, like helper pixels on a GPU, so derivatives exist up to the edges.
+pub struct Coverage {
+    pub x: usize,
+    pub y: usize,
+    pub depth: f32,
+    pub barycentric: Vec3,
+    pub quad: [Vec3; 4],
+    pub mask: u32,
+    pub sample_depths: [f32; MAX_SAMPLES],
+}
+
+// Offsets of the pixels of a quad from its top-left pixel
+const QUAD_PIXELS: [(i64, i64); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+// Screen coordinates are snapped to a 1/256 pixel grid before rasterizing
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+
+// Fixed-point edge function for the edge from `p` to `q`, oriented so the
+// triangle interior is positive. `bias` implements the top-left fill rule.
+struct Edge {
+    step_x: i64,
+    step_y: i64,
+    bias: i64,
+}
+
+impl Edge {
+    fn new(p: (i64, i64), q: (i64, i64), orientation: i64) -> Self {
+        let dx = (q.0 - p.0) * orientation;
+        let dy = (q.1 - p.1) * orientation;
+
+        // With y pointing down, a left edge goes downwards and a top edge is
+        // horizontal going left. Pixels exactly on any other edge belong to the
+        // neighbouring triangle, so a bias of -1 turns >= 0 into > 0 for them.
+        let is_top_left = dy > 0 || (dy == 0 && dx < 0);
+
+        Edge {
+            step_x: dy,
+            step_y: -dx,
+            bias: if is_top_left { 0 } else { -1 },
+        }
+    }
+
+    // Edge function value at a fixed-point position, relative to a point on the edge
+    fn evaluate(&self, p: (i64, i64), origin: (i64, i64)) -> i64 {
+        (p.0 - origin.0) * self.step_x + (p.1 - origin.1) * self.step_y
+    }
+}
+
+fn to_fixed(position: &Vec4) -> (i64, i64) {
+    (
+        (position.x * SUBPIXEL_ONE as f32).round() as i64,
+        (position.y * SUBPIXEL_ONE as f32).round() as i64,
+    )
+}
+
+// Walks the pixels covered by the triangle inside `columns` and `rows` and hands each one to `emit` as soon as it is found.
+// Vertices are snapped to fixed point and edge functions are stepped incrementally with integers, so
+// adjacent triangles agree exactly on their shared edges and the top-left rule assigns every sample
+// to exactly one of them. Coverage is tested at each position of `sample_pattern`.
+pub fn triangle<F: FnMut(&Coverage)>(v1: &Vertex, v2: &Vertex, v3: &Vertex, columns: Range<usize>, rows: Range<usize>, sample_pattern: &[(f32, f32)], mut emit: F) {
     let (a, b, c) = (v1.transformed_position, v2.transformed_position, v3.transformed_position);
-    let (t1, t2, t3) = (v1.tex_coords, v2.tex_coords, v3.tex_coords);
+    let (fa, fb, fc) = (to_fixed(&a), to_fixed(&b), to_fixed(&c));
 
-    let (min_x, min_y, max_x, max_y) = calculate_bounding_box(&a, &b, &c);
+    let area = (fc.0 - fa.0) * (fb.1 - fa.1) - (fc.1 - fa.1) * (fb.0 - fa.0);
+    if area == 0 {
+        return;
+    }
+    let orientation = area.signum();
+    let triangle_area = (area * orientation) as f32;
+
+    // One edge per barycentric weight: w1 is opposite to a, w2 to b and w3 to c
+    let edges = [
+        Edge::new(fb, fc, orientation),
+        Edge::new(fc, fa, orientation),
+        Edge::new(fa, fb, orientation),
+    ];
+    let origins = [fb, fc, fa];
+
+    // Offset of every sample from the pixel center, in fixed point and as edge function deltas
+    let offsets: Vec<(i64, i64)> = sample_pattern
+        .iter()
+        .map(|&(ox, oy)| ((ox * SUBPIXEL_ONE as f32).round() as i64, (oy * SUBPIXEL_ONE as f32).round() as i64))
+        .collect();
+    let sample_deltas: Vec<[i64; 3]> = offsets
+        .iter()
+        .map(|&(ox, oy)| [0, 1, 2].map(|i| edges[i].step_x * ox + edges[i].step_y * oy))
+        .collect();
+    let reach = offsets.iter().map(|&(ox, oy)| ox.abs().max(oy.abs())).max().unwrap_or(0);
+
+    // Bounding box of the pixels with a sample inside the triangle, clipped to the tile
+    let min_x = ((fa.0.min(fb.0).min(fc.0) - SUBPIXEL_HALF - reach) >> SUBPIXEL_BITS).max(columns.start as i64);
+    let max_x = ((fa.0.max(fb.0).max(fc.0) - SUBPIXEL_HALF + reach) >> SUBPIXEL_BITS).min(columns.end as i64 - 1);
+    let min_y = ((fa.1.min(fb.1).min(fc.1) - SUBPIXEL_HALF - reach) >> SUBPIXEL_BITS).max(rows.start as i64);
+    let max_y = ((fa.1.max(fb.1).max(fc.1) - SUBPIXEL_HALF + reach) >> SUBPIXEL_BITS).min(rows.end as i64 - 1);
 
-    let light_dir = Vec3::new(0.0, 0.0, 1.0);
+    if min_x > max_x || min_y > max_y {
+        return;
+    }
 
-    let triangle_area = edge_function(&a, &b, &c);
+    // Pixels are walked in 2x2 quads aligned to even coordinates
+    let (quad_min_x, quad_min_y) = (min_x & !1, min_y & !1);
 
-    // Iterate over each pixel in the bounding box
-    for y in min_y..=max_y {
-        for x in min_x..=max_x {
-            let point = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+    // Edge values at the center of the first pixel of the first quad
+    let start = (quad_min_x * SUBPIXEL_ONE + SUBPIXEL_HALF, quad_min_y * SUBPIXEL_ONE + SUBPIXEL_HALF);
+    let mut row_values = [0i64; 3];
+    for i in 0..3 {
+        row_values[i] = edges[i].evaluate(start, origins[i]) + edges[i].bias;
+    }
 
-            // Calculate barycentric coordinates
-            let (w1, w2, w3) = barycentric_coordinates(&point, &a, &b, &c, triangle_area);
+    // Barycentric weights from biased edge values; undoing the bias keeps them exact
+    let weights = |values: [i64; 3]| {
+        Vec3::new(
+            (values[0] - edges[0].bias) as f32 / triangle_area,
+            (values[1] - edges[1].bias) as f32 / triangle_area,
+            (values[2] - edges[2].bias) as f32 / triangle_area,
+        )
+    };
 
-            // Check if the point is inside the triangle
-            if w1 >= 0.0 && w1 <= 1.0 && 
-            w2 >= 0.0 && w2 <= 1.0 &&
-            w3 >= 0.0 && w3 <= 1.0 {
+    for quad_y in (quad_min_y..=max_y).step_by(2) {
+        let mut quad_values = row_values;
 
-                // Interpolate normal
-                let normal = v1.transformed_normal * w1 + v2.transformed_normal * w2 + v3.transformed_normal * w3;
-                let normal = normal.normalize();
+        for quad_x in (quad_min_x..=max_x).step_by(2) {
+            let pixel_values = QUAD_PIXELS.map(|(dx, dy)| {
+                [0, 1, 2].map(|i| quad_values[i] + (edges[i].step_x * dx + edges[i].step_y * dy) * SUBPIXEL_ONE)
+            });
+            // Only worked out once a pixel of the quad turns out to be covered
+            let mut quad = None;
 
-                // Calculate lighting intensity
-                let intensity = dot(&normal, &light_dir).max(0.0);
+            for (values, (dx, dy)) in pixel_values.into_iter().zip(QUAD_PIXELS) {
+                let (x, y) = (quad_x + dx, quad_y + dy);
+                if x < min_x || x > max_x || y < min_y || y > max_y {
+                    continue;
+                }
 
-                // Create a gray color (unchanged)
-                let color = Color::new(100, 100, 100); // Medium gray
+                let mut mask = 0;
+                let mut sample_depths = [f32::INFINITY; MAX_SAMPLES];
 
-                // Interpolate depth
-                let depth = a.z * w1 + b.z * w2 + c.z * w3;
+                for (s, deltas) in sample_deltas.iter().enumerate() {
+                    let sample = [values[0] + deltas[0], values[1] + deltas[1], values[2] + deltas[2]];
+                    if sample[0] >= 0 && sample[1] >= 0 && sample[2] >= 0 {
+                        // Interpolate depth; z/w is already linear in screen space
+                        let w = weights(sample);
+                        sample_depths[s] = a.z * w.x + b.z * w.y + c.z * w.z;
+                        mask |= 1 << s;
+                    }
+                }
 
-                // Positions of the original vertex
-                let vertex_position = v1.position * w1 + v2.position * w2 + v3.position * w3;
+                if mask != 0 {
+                    let quad = *quad.get_or_insert_with(|| pixel_values.map(weights));
+                    let barycentric = weights(values);
 
-                // Interpolate texture coordinates
-                let tex_u = t1.x * w1 + t2.x * w2 + t3.x * w3;
-                let tex_v = t1.y * w1 + t2.y * w2 + t3.y * w3;
+                    emit(&Coverage {
+                        x: x as usize,
+                        y: y as usize,
+                        depth: a.z * barycentric.x + b.z * barycentric.y + c.z * barycentric.z,
+                        barycentric,
+                        quad,
+                        mask,
+                        sample_depths,
+                    });
+                }
+            }
 
-                fragments.push(Fragment::new(
-                    Vec2::new(x as f32, y as f32),
-                    color,
-                    depth,
-                    normal,
-                    intensity,
-                    vertex_position,
-                    Vec2::new(tex_u, tex_v),
-                ));
+            for i in 0..3 {
+                quad_values[i] += edges[i].step_x * 2 * SUBPIXEL_ONE;
             }
         }
+
+        for i in 0..3 {
+            row_values[i] += edges[i].step_y * 2 * SUBPIXEL_ONE;
+        }
+    }
+}
+
+// Builds the fragment for a covered pixel, interpolating every varying of the triangle
+pub fn interpolate_fragment(v1: &Vertex, v2: &Vertex, v3: &Vertex, coverage: &Coverage, interpolation: Interpolation) -> Fragment {
+    let (a, b, c) = (v1.transformed_position, v2.transformed_position, v3.transformed_position);
+    let (t1, t2, t3) = (v1.tex_coords, v2.tex_coords, v3.tex_coords);
+    let bary = coverage.barycentric;
+
+    // Weights used for every varying
+    let (w1, w2, w3) = match interpolation {
+        Interpolation::Perspective => perspective_correct(bary.x, bary.y, bary.z, &a, &b, &c),
+        Interpolation::Affine => (bary.x, bary.y, bary.z),
+    };
+
+    // Interpolate normal
+    let normal = v1.transformed_normal * w1 + v2.transformed_normal * w2 + v3.transformed_normal * w3;
+    let normal = normal.normalize();
+    let tangent = v1.transformed_tangent * w1 + v2.transformed_tangent * w2 + v3.transformed_tangent * w3;
+    let bitangent = v1.transformed_bitangent * w1 + v2.transformed_bitangent * w2 + v3.transformed_bitangent * w3;
+
+    // Create a gray color (unchanged)
+    let color = Color::new(100, 100, 100); // Medium gray
+
+    // Positions of the original vertex
+    let vertex_position = v1.position * w1 + v2.position * w2 + v3.position * w3;
+    let world_position = v1.world_position * w1 + v2.world_position * w2 + v3.world_position * w3;
+
+    // Interpolate texture coordinates
+    let tex_u = t1.x * w1 + t2.x * w2 + t3.x * w3;
+    let tex_v = t1.y * w1 + t2.y * w2 + t3.y * w3;
+
+    // Coarse derivatives, shared by the whole quad: differences along its top row and left column
+    let quad = coverage.quad.map(|bary| match interpolation {
+        Interpolation::Perspective => perspective_correct(bary.x, bary.y, bary.z, &a, &b, &c),
+        Interpolation::Affine => (bary.x, bary.y, bary.z),
+    });
+    let tex_coords_at = |(w1, w2, w3): (f32, f32, f32)| t1 * w1 + t2 * w2 + t3 * w3;
+    let vertex_position_at = |(w1, w2, w3): (f32, f32, f32)| v1.position * w1 + v2.position * w2 + v3.position * w3;
+
+    Fragment {
+        position: Vec2::new(coverage.x as f32, coverage.y as f32),
+        color,
+        depth: coverage.depth,
+        normal,
+        tangent,
+        bitangent,
+        vertex_position,
+        world_position,
+        tex_coords: Vec2::new(tex_u, tex_v),
+        tex_coords_dx: tex_coords_at(quad[1]) - tex_coords_at(quad[0]),
+        tex_coords_dy: tex_coords_at(quad[2]) - tex_coords_at(quad[0]),
+        vertex_position_dx: vertex_position_at(quad[1]) - vertex_position_at(quad[0]),
+        vertex_position_dy: vertex_position_at(quad[2]) - vertex_position_at(quad[0]),
+    }
+}
+
+// Columns and rows of the screen touched by the triangle's bounding box, used to bin it into tiles
+pub fn pixel_bounds(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> (Range<usize>, Range<usize>) {
+    let (a, b, c) = (v1.transformed_position, v2.transformed_position, v3.transformed_position);
+    let (min_x, min_y, max_x, max_y) = calculate_bounding_box(&a, &b, &c);
+
+    (
+        min_x.max(0) as usize..(max_x + 1).max(0) as usize,
+        min_y.max(0) as usize..(max_y + 1).max(0) as usize,
+    )
+}
+
+// Signed screen-space area (times two) of a triangle, positive for counter-clockwise winding in NDC
+pub fn signed_area(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> f32 {
+    edge_function(&v1.transformed_position, &v2.transformed_position, &v3.transformed_position)
+}
+
+// Weights each screen-space barycentric by the vertex 1/w (stored in transformed_position.w)
+fn perspective_correct(w1: f32, w2: f32, w3: f32, a: &Vec4, b: &Vec4, c: &Vec4) -> (f32, f32, f32) {
+    let p1 = w1 * a.w;
+    let p2 = w2 * b.w;
+    let p3 = w3 * c.w;
+    let sum = p1 + p2 + p3;
+
+    if sum.abs() <= f32::EPSILON {
+        return (w1, w2, w3);
     }
 
-    fragments
+    (p1 / sum, p2 / sum, p3 / sum)
 }
 
-fn calculate_bounding_box(v1: &Vec3, v2: &Vec3, v3: &Vec3) -> (i32, i32, i32, i32) {
+fn calculate_bounding_box(v1: &Vec4, v2: &Vec4, v3: &Vec4) -> (i32, i32, i32, i32) {
     let min_x = v1.x.min(v2.x).min(v3.x).floor() as i32;
     let min_y = v1.y.min(v2.y).min(v3.y).floor() as i32;
     let max_x = v1.x.max(v2.x).max(v3.x).ceil() as i32;
@@ -72,16 +298,220 @@ fn calculate_bounding_box(v1: &Vec3, v2: &Vec3, v3: &Vec3) -> (i32, i32, i32, i3
     (min_x, min_y, max_x, max_y)
 }
 
-fn barycentric_coordinates(p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3, area: f32) -> (f32, f32, f32) {
-    let w1 = edge_function(b, c, p) / area;
-    let w2 = edge_function(c, a, p) / area;
-    let w3 = edge_function(a, b, p) / area;
-
-    (w1, w2, w3)
-}
-
-fn edge_function(a: &Vec3, b: &Vec3, c: &Vec3) -> f32 {
+fn edge_function(a: &Vec4, b: &Vec4, c: &Vec4) -> f32 {
     (c.x - a.x) * (b.y - a.y) - (c.y - a.y) * (b.x - a.x)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::sample_pattern;
+
+    fn screen_vertex(x: f32, y: f32) -> Vertex {
+        Vertex {
+            transformed_position: Vec4::new(x, y, 0.5, 1.0),
+            ..Default::default()
+        }
+    }
+
+    // Rasterizes every triangle into a width x height grid and counts how often each sample is hit
+    fn sample_counts(triangles: &[[(f32, f32); 3]], width: usize, height: usize, samples: usize) -> Vec<u32> {
+        let mut counts = vec![0; width * height * samples];
+
+        for [p1, p2, p3] in triangles {
+            let (v1, v2, v3) = (screen_vertex(p1.0, p1.1), screen_vertex(p2.0, p2.1), screen_vertex(p3.0, p3.1));
+            triangle(&v1, &v2, &v3, 0..width, 0..height, sample_pattern(samples), |coverage| {
+                if coverage.x < width {
+                    for s in 0..samples {
+                        if coverage.mask & (1 << s) != 0 {
+                            counts[(coverage.y * width + coverage.x) * samples + s] += 1;
+                        }
+                    }
+                }
+            });
+        }
+
+        counts
+    }
+
+    fn coverage_counts(triangles: &[[(f32, f32); 3]], width: usize, height: usize) -> Vec<u32> {
+        sample_counts(triangles, width, height, 1)
+    }
+
+    // Splits a grid of quads into triangles. Inner grid points are jittered with a
+    // deterministic sequence, the border stays on the rectangle so it is fully covered.
+    // Jitter is snapped to half pixels so many edges run exactly through pixel centers.
+    fn jittered_grid(cells_x: usize, cells_y: usize, cell_size: f32, jitter: f32, flip_winding: bool) -> Vec<[(f32, f32); 3]> {
+        let mut seed = 12345u32;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+        };
 
+        let mut points = Vec::new();
+        for j in 0..=cells_y {
+            for i in 0..=cells_x {
+                let mut x = i as f32 * cell_size;
+                let mut y = j as f32 * cell_size;
+                if i > 0 && i < cells_x {
+                    x += (next() * jitter * 2.0).round() / 2.0;
+                }
+                if j > 0 && j < cells_y {
+                    y += (next() * jitter * 2.0).round() / 2.0;
+                }
+                points.push((x, y));
+            }
+        }
+
+        let point = |i: usize, j: usize| points[j * (cells_x + 1) + i];
+        let mut triangles = Vec::new();
+        for j in 0..cells_y {
+            for i in 0..cells_x {
+                let (p00, p10, p01, p11) = (point(i, j), point(i + 1, j), point(i, j + 1), point(i + 1, j + 1));
+                // Alternate the diagonal so both orientations of shared edges are exercised
+                let mut pair = if (i + j) % 2 == 0 {
+                    [[p00, p10, p11], [p00, p11, p01]]
+                } else {
+                    [[p00, p10, p01], [p10, p11, p01]]
+                };
+                if flip_winding {
+                    for tri in pair.iter_mut() {
+                        tri.swap(1, 2);
+                    }
+                }
+                triangles.extend(pair);
+            }
+        }
+
+        triangles
+    }
+
+    #[test]
+    fn square_split_along_diagonal_covers_each_pixel_once() {
+        let triangles = [
+            [(0.0, 0.0), (8.0, 0.0), (8.0, 8.0)],
+            [(0.0, 0.0), (8.0, 8.0), (0.0, 8.0)],
+        ];
+
+        let counts = coverage_counts(&triangles, 8, 8);
+        assert!(counts.iter().all(|&count| count == 1), "{:?}", counts);
+    }
+
+    #[test]
+    fn jittered_mesh_is_watertight() {
+        for flip_winding in [false, true] {
+            let triangles = jittered_grid(12, 9, 5.0, 4.0, flip_winding);
+            let counts = coverage_counts(&triangles, 60, 45);
+
+            for (i, &count) in counts.iter().enumerate() {
+                assert_eq!(count, 1, "pixel ({}, {}) covered {} times", i % 60, i / 60, count);
+            }
+        }
+    }
+
+    #[test]
+    fn jittered_mesh_is_watertight_for_every_msaa_sample() {
+        for samples in [2, 4, 8] {
+            let triangles = jittered_grid(12, 9, 5.0, 4.0, false);
+            let counts = sample_counts(&triangles, 60, 45, samples);
+
+            for (i, &count) in counts.iter().enumerate() {
+                let pixel = i / samples;
+                assert_eq!(count, 1, "{}x sample {} of pixel ({}, {}) covered {} times", samples, i % samples, pixel % 60, pixel / 60, count);
+            }
+        }
+    }
+
+    #[test]
+    fn fan_of_slivers_has_no_holes_or_overlaps() {
+        // Many thin triangles around a center that is not on the pixel grid
+        let center = (16.3, 15.7);
+        let segments = 97;
+        let radius = 40.0;
+        let ring: Vec<(f32, f32)> = (0..segments)
+            .map(|i| {
+                let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+                (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+            })
+            .collect();
+
+        let triangles: Vec<[(f32, f32); 3]> = (0..segments)
+            .map(|i| [center, ring[i], ring[(i + 1) % segments]])
+            .collect();
+
+        // The fan covers the whole 32x32 area around its center
+        let counts = coverage_counts(&triangles, 32, 32);
+        assert!(counts.iter().all(|&count| count == 1), "{:?}", counts);
+    }
+
+    #[test]
+    fn top_left_rule_owns_pixel_centers_on_edges() {
+        // Pixel centers of row 1 lie exactly on the shared horizontal edge y = 1.5
+        let above = [[(0.0, 0.0), (4.0, 1.5), (0.0, 1.5)], [(0.0, 0.0), (4.0, 0.0), (4.0, 1.5)]];
+        let below = [[(0.0, 1.5), (4.0, 1.5), (4.0, 4.0)], [(0.0, 1.5), (4.0, 4.0), (0.0, 4.0)]];
+
+        let counts_above = coverage_counts(&above, 4, 4);
+        let counts_below = coverage_counts(&below, 4, 4);
+
+        // The edge is the bottom of the upper quad and the top of the lower one
+        for x in 0..4 {
+            assert_eq!(counts_above[4 + x], 0);
+            assert_eq!(counts_below[4 + x], 1);
+        }
+
+        // Pixel centers of column 2 lie on the vertical edge x = 2.5: the right triangle owns them
+        let left = [[(0.0, 0.0), (2.5, 0.0), (2.5, 4.0)], [(0.0, 0.0), (2.5, 4.0), (0.0, 4.0)]];
+        let right = [[(2.5, 0.0), (4.0, 0.0), (4.0, 4.0)], [(2.5, 0.0), (4.0, 4.0), (2.5, 4.0)]];
+
+        let counts_left = coverage_counts(&left, 4, 4);
+        let counts_right = coverage_counts(&right, 4, 4);
+        for y in 0..4 {
+            assert_eq!(counts_left[y * 4 + 2], 0);
+            assert_eq!(counts_right[y * 4 + 2], 1);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangle_covers_nothing() {
+        let counts = coverage_counts(&[[(0.0, 0.0), (4.0, 4.0), (8.0, 8.0)]], 8, 8);
+        assert!(counts.iter().all(|&count| count == 0));
+    }
+
+    #[test]
+    fn perspective_correct_varyings_follow_depth_on_a_receding_quad() {
+        // A floor quad one unit below the eye, from z = 1 to z = 3, projected with a
+        // focal length of 16 pixels. v runs from 0 on the near edge to 1 on the far one.
+        let focal = 16.0;
+        let corner = |x: f32, z: f32, v: f32| Vertex {
+            transformed_position: Vec4::new(16.0 + focal * x / z, focal / z, 0.5, 1.0 / z),
+            tex_coords: Vec2::new(0.0, v),
+            ..Default::default()
+        };
+        let (near_left, near_right) = (corner(-1.0, 1.0, 0.0), corner(1.0, 1.0, 0.0));
+        let (far_left, far_right) = (corner(-1.0, 3.0, 1.0), corner(1.0, 3.0, 1.0));
+        let quad = [[&far_left, &far_right, &near_right], [&far_left, &near_right, &near_left]];
+
+        // Pixel (16, 8) has its center at y = 8.5, so the floor there is at z = 16 / 8.5
+        let (x, y) = (16, 8);
+        let mut values = Vec::new();
+        for [v1, v2, v3] in quad {
+            triangle(v1, v2, v3, 0..32, 0..32, sample_pattern(1), |coverage| {
+                if (coverage.x, coverage.y) == (x, y) {
+                    let v = |interpolation| interpolate_fragment(v1, v2, v3, coverage, interpolation).tex_coords.y;
+                    values.push((v(Interpolation::Perspective), v(Interpolation::Affine)));
+                }
+            });
+        }
+        assert_eq!(values.len(), 1);
+        let (perspective, affine) = values[0];
+
+        let z = focal / (y as f32 + 0.5);
+        let expected = (z - 1.0) / 2.0;
+        assert!((perspective - expected).abs() < 1e-3, "{} != {}", perspective, expected);
+
+        // Screen-space interpolation runs linearly from the near edge at y = 16 to the far one at y = 16 / 3
+        let screen = (16.0 - (y as f32 + 0.5)) / (16.0 - 16.0 / 3.0);
+        assert!((affine - screen).abs() < 1e-3, "{} != {}", affine, screen);
+        assert!((perspective - affine).abs() > 0.1);
+    }
+}
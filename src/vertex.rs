@@ -1,25 +1,41 @@
-use nalgebra_glm::{Vec2, Vec3};
+use nalgebra_glm::{Vec2, Vec3, Vec4};
 use crate::color::Color;
 
 #[derive(Clone, Debug)]
 pub struct Vertex {
   pub position: Vec3,
   pub normal: Vec3,
+  // Directions of increasing u and v on the surface, for tangent-space normals
+  pub tangent: Vec3,
+  pub bitangent: Vec3,
   pub tex_coords: Vec2,
   pub color: Color,
-  pub transformed_position: Vec3,
+  pub clip_position: Vec4,
+  // Screen-space x, y and depth, with 1/w of the clip position kept in w
+  pub transformed_position: Vec4,
   pub transformed_normal: Vec3,
+  pub transformed_tangent: Vec3,
+  pub transformed_bitangent: Vec3,
+  // Position after the model matrix, used for lighting
+  pub world_position: Vec3,
 }
 
 impl Vertex {
+  // Tangents are left at zero until the mesh computes them from its triangles
   pub fn new(position: Vec3, normal: Vec3, tex_coords: Vec2) -> Self {
     Vertex {
       position,
       normal,
+      tangent: Vec3::zeros(),
+      bitangent: Vec3::zeros(),
       tex_coords,
       color: Color::black(),
-      transformed_position: position,
+      clip_position: Vec4::new(position.x, position.y, position.z, 1.0),
+      transformed_position: Vec4::new(position.x, position.y, position.z, 1.0),
       transformed_normal: normal,
+      transformed_tangent: Vec3::zeros(),
+      transformed_bitangent: Vec3::zeros(),
+      world_position: position,
     }
   }
 
@@ -27,17 +43,41 @@ impl Vertex {
     Vertex {
       position,
       normal: Vec3::new(0.0, 0.0, 0.0),
+      tangent: Vec3::new(0.0, 0.0, 0.0),
+      bitangent: Vec3::new(0.0, 0.0, 0.0),
       tex_coords: Vec2::new(0.0, 0.0),
       color,
-      transformed_position: Vec3::new(0.0, 0.0, 0.0),
+      clip_position: Vec4::new(0.0, 0.0, 0.0, 1.0),
+      transformed_position: Vec4::new(0.0, 0.0, 0.0, 1.0),
       transformed_normal: Vec3::new(0.0, 0.0, 0.0),
+      transformed_tangent: Vec3::new(0.0, 0.0, 0.0),
+      transformed_bitangent: Vec3::new(0.0, 0.0, 0.0),
+      world_position: position,
     }
   }
 
-  pub fn set_transformed(&mut self, position: Vec3, normal: Vec3) {
+  pub fn set_transformed(&mut self, position: Vec4, normal: Vec3) {
     self.transformed_position = position;
     self.transformed_normal = normal;
   }
+
+  // Linear interpolation of every attribute, used to build the new vertices created by clipping
+  pub fn lerp(&self, other: &Vertex, t: f32) -> Self {
+    Vertex {
+      position: self.position.lerp(&other.position, t),
+      normal: self.normal.lerp(&other.normal, t),
+      tangent: self.tangent.lerp(&other.tangent, t),
+      bitangent: self.bitangent.lerp(&other.bitangent, t),
+      tex_coords: self.tex_coords.lerp(&other.tex_coords, t),
+      color: self.color.lerp(&other.color, t),
+      clip_position: self.clip_position.lerp(&other.clip_position, t),
+      transformed_position: self.transformed_position.lerp(&other.transformed_position, t),
+      transformed_normal: self.transformed_normal.lerp(&other.transformed_normal, t),
+      transformed_tangent: self.transformed_tangent.lerp(&other.transformed_tangent, t),
+      transformed_bitangent: self.transformed_bitangent.lerp(&other.transformed_bitangent, t),
+      world_position: self.world_position.lerp(&other.world_position, t),
+    }
+  }
 }
 
 impl Default for Vertex {
@@ -45,10 +85,16 @@ impl Default for Vertex {
     Vertex {
       position: Vec3::new(0.0, 0.0, 0.0),
       normal: Vec3::new(0.0, 1.0, 0.0),
+      tangent: Vec3::new(1.0, 0.0, 0.0),
+      bitangent: Vec3::new(0.0, 0.0, 1.0),
       tex_coords: Vec2::new(0.0, 0.0),
       color: Color::black(),
-      transformed_position: Vec3::new(0.0, 0.0, 0.0),
+      clip_position: Vec4::new(0.0, 0.0, 0.0, 1.0),
+      transformed_position: Vec4::new(0.0, 0.0, 0.0, 1.0),
       transformed_normal: Vec3::new(0.0, 1.0, 0.0),
+      transformed_tangent: Vec3::new(1.0, 0.0, 0.0),
+      transformed_bitangent: Vec3::new(0.0, 0.0, 1.0),
+      world_position: Vec3::new(0.0, 0.0, 0.0),
     }
   }
 }